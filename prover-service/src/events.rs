@@ -0,0 +1,141 @@
+use std::{
+    collections::HashMap,
+    sync::mpsc::{SendError, Sender},
+    time::Instant,
+};
+
+use super::*;
+
+/// A report together with the context of the job it belongs to.
+#[derive(Clone, Debug)]
+pub struct JobEvent {
+    /// Monotonic time since the service started
+    pub timestamp: Duration,
+    pub job_id: Option<JobId>,
+    pub circuit_id: Option<u8>,
+    pub stage: Option<JobStage>,
+    pub prover_idx: Option<ProverId>,
+    /// Devices of the prover, empty for reports without a prover
+    pub device_ids: Vec<usize>,
+    /// Proving attempt of the job starting at 1, 0 for reports without a job
+    pub attempt: usize,
+    pub result: JobResult,
+}
+
+#[derive(Clone)]
+struct JobContext {
+    circuit_id: u8,
+    stage: Option<JobStage>,
+    prover_idx: Option<ProverId>,
+    attempt: usize,
+}
+
+/// Context of the jobs in flight, updated as they move through the stages.
+#[derive(Default)]
+pub(crate) struct JobContexts {
+    jobs: Mutex<HashMap<JobId, JobContext>>,
+    prover_device_ids: Mutex<HashMap<ProverId, Vec<usize>>>,
+}
+
+impl JobContexts {
+    pub(crate) fn start(&self, job_id: JobId, circuit_id: u8) {
+        let context = JobContext {
+            circuit_id,
+            stage: None,
+            prover_idx: None,
+            attempt: 1,
+        };
+        self.jobs.lock().unwrap().insert(job_id, context);
+    }
+
+    pub(crate) fn enter_stage(&self, job_id: JobId, stage: JobStage, prover_idx: Option<ProverId>) {
+        if let Some(context) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            context.stage = Some(stage);
+            context.prover_idx = prover_idx;
+        }
+    }
+
+    pub(crate) fn set_attempt(&self, job_id: JobId, attempt: usize) {
+        if let Some(context) = self.jobs.lock().unwrap().get_mut(&job_id) {
+            context.attempt = attempt;
+        }
+    }
+
+    pub(crate) fn finish(&self, job_id: JobId) {
+        self.jobs.lock().unwrap().remove(&job_id);
+    }
+
+    pub(crate) fn set_prover_device_ids(&self, prover_idx: ProverId, device_ids: Vec<usize>) {
+        self.prover_device_ids
+            .lock()
+            .unwrap()
+            .insert(prover_idx, device_ids);
+    }
+}
+
+/// Stamps every report with the time and the context of its job before
+/// it is queued for the reporter.
+#[derive(Clone)]
+pub struct ReportSender {
+    sender: Sender<JobEvent>,
+    contexts: Arc<JobContexts>,
+    started: Instant,
+}
+
+impl ReportSender {
+    pub(crate) fn new(sender: Sender<JobEvent>, contexts: Arc<JobContexts>) -> Self {
+        Self {
+            sender,
+            contexts,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn send(&self, result: JobResult) -> Result<(), SendError<JobEvent>> {
+        self.send_for(result.job_id(), result)
+    }
+
+    /// For reports that don't name the job they were produced for
+    pub fn send_with_job(
+        &self,
+        job_id: JobId,
+        result: JobResult,
+    ) -> Result<(), SendError<JobEvent>> {
+        self.send_for(Some(job_id), result)
+    }
+
+    fn send_for(
+        &self,
+        job_id: Option<JobId>,
+        result: JobResult,
+    ) -> Result<(), SendError<JobEvent>> {
+        let context =
+            job_id.and_then(|job_id| self.contexts.jobs.lock().unwrap().get(&job_id).cloned());
+        let prover_idx = match result {
+            JobResult::ProverWaitedIdle(prover_idx, _)
+            | JobResult::ProofGenerated(_, _, _, prover_idx) => Some(prover_idx),
+            _ => context.as_ref().and_then(|context| context.prover_idx),
+        };
+        let device_ids = prover_idx
+            .and_then(|prover_idx| {
+                self.contexts
+                    .prover_device_ids
+                    .lock()
+                    .unwrap()
+                    .get(&prover_idx)
+                    .cloned()
+            })
+            .unwrap_or_default();
+
+        self.sender.send(JobEvent {
+            timestamp: self.started.elapsed(),
+            job_id,
+            circuit_id: context.as_ref().map(|context| context.circuit_id),
+            stage: context.as_ref().and_then(|context| context.stage),
+            prover_idx,
+            device_ids,
+            attempt: context.as_ref().map_or(0, |context| context.attempt),
+            result,
+        })
+    }
+}
@@ -1,18 +1,25 @@
 #![feature(get_mut_unchecked)]
 #![cfg_attr(feature = "gpu", feature(allocator_api))]
+pub mod events;
 pub mod remote_synth;
+pub mod reporters;
+pub mod routing;
 pub mod run_prover;
+pub(crate) mod scheduling;
 pub(crate) mod setup;
+pub mod setup_cache;
 pub mod simple;
 #[cfg(test)]
 mod tests;
+pub mod transport;
 pub mod utils;
+pub mod worker_pool;
 
 pub use bellman::bn256::{Bn256, Fr};
 
 use std::io::Read;
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Condvar, Mutex};
 use std::time::Duration;
 use zkevm_test_harness::abstract_zksync_circuit::concrete_circuits::ZkSyncProof;
 pub use zkevm_test_harness::abstract_zksync_circuit::concrete_circuits::ZkSyncVerificationKey;
@@ -55,6 +62,17 @@ pub enum ProverError {
 
 pub type JobId = usize;
 pub type ProverId = usize;
+/// Jobs with higher priority are scheduled and proven first
+pub type JobPriority = u32;
+
+/// Stages a job passes through on the prover, each can have its own deadline
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum JobStage {
+    Synthesis,
+    Decoding,
+    SetupLoading,
+    Proving,
+}
 
 #[derive(Clone)]
 pub enum JobResult {
@@ -66,10 +84,45 @@ pub enum JobResult {
     AssemblyEncoded(JobId, std::time::Duration),
     AssemblyDecoded(JobId, std::time::Duration),
     AssemblyTransferred(JobId, std::time::Duration),
+    AssemblyCorrupted(JobId, String),
+    Cancelled(JobId),
     FailureWithDebugging(JobId, u8, Vec<u8>, String),
     ProverWaitedIdle(ProverId, std::time::Duration),
     SetupLoaderWaitedIdle(std::time::Duration),
     SchedulerWaitedIdle(std::time::Duration),
+    /// Setups of the preloaded circuits are resident and the scheduler starts taking jobs
+    ServiceReady(std::time::Duration),
+    /// Attempt that failed and the reason, the job is proven or its assembly is
+    /// delivered again after a backoff
+    RetryScheduled(JobId, usize, String),
+    /// Number of attempts made, followed by the report of the last failure.
+    /// Sent for every failure the service won't retry, job managers don't hand the job out again
+    RetriesExhausted(JobId, usize),
+}
+
+impl JobResult {
+    /// Job the report belongs to, `None` for reports about the service itself
+    pub fn job_id(&self) -> Option<JobId> {
+        match self {
+            Self::Synthesized(job_id, _)
+            | Self::AssemblyFinalized(job_id, _)
+            | Self::SetupLoaded(job_id, _, _)
+            | Self::ProofGenerated(job_id, _, _, _)
+            | Self::Failure(job_id, _)
+            | Self::AssemblyEncoded(job_id, _)
+            | Self::AssemblyDecoded(job_id, _)
+            | Self::AssemblyTransferred(job_id, _)
+            | Self::AssemblyCorrupted(job_id, _)
+            | Self::Cancelled(job_id)
+            | Self::FailureWithDebugging(job_id, _, _, _)
+            | Self::RetryScheduled(job_id, _, _)
+            | Self::RetriesExhausted(job_id, _) => Some(*job_id),
+            Self::ProverWaitedIdle(_, _)
+            | Self::SetupLoaderWaitedIdle(_)
+            | Self::SchedulerWaitedIdle(_)
+            | Self::ServiceReady(_) => None,
+        }
+    }
 }
 
 impl std::fmt::Debug for JobResult {
@@ -91,6 +144,18 @@ impl std::fmt::Debug for JobResult {
             Self::SchedulerWaitedIdle(arg0) => {
                 f.debug_tuple("SchedulerWaitedIdle").field(arg0).finish()
             }
+            Self::ServiceReady(arg0) => f.debug_tuple("ServiceReady").field(arg0).finish(),
+            Self::RetryScheduled(arg0, arg1, arg2) => f
+                .debug_tuple("RetryScheduled")
+                .field(arg0)
+                .field(arg1)
+                .field(arg2)
+                .finish(),
+            Self::RetriesExhausted(arg0, arg1) => f
+                .debug_tuple("RetriesExhausted")
+                .field(arg0)
+                .field(arg1)
+                .finish(),
             Self::AssemblyFinalized(arg0, arg1) => f
                 .debug_tuple("AssemblyFinalized")
                 .field(arg0)
@@ -123,6 +188,12 @@ impl std::fmt::Debug for JobResult {
                 .field(arg0)
                 .field(&arg1)
                 .finish(),
+            Self::AssemblyCorrupted(arg0, arg1) => f
+                .debug_tuple("AssemblyCorrupted")
+                .field(arg0)
+                .field(arg1)
+                .finish(),
+            Self::Cancelled(arg0) => f.debug_tuple("Cancelled").field(arg0).finish(),
             Self::Failure(arg0, arg1) => f.debug_tuple("Failure").field(arg0).field(arg1).finish(),
             Self::FailureWithDebugging(arg0, arg1, arg2, arg3) => {
                 f.debug_tuple("Failure").field(arg0).field(arg1).finish()
@@ -133,6 +204,11 @@ impl std::fmt::Debug for JobResult {
 
 pub trait JobReporter: Send {
     fn send_report(&mut self, report: JobResult);
+    /// Receives every report with the context of its job, reporters that only
+    /// implement `send_report` get the bare report.
+    fn send_event(&mut self, event: events::JobEvent) {
+        self.send_report(event.result)
+    }
 }
 
 pub trait JobManager: Send {
@@ -144,6 +220,91 @@ pub trait JobManager: Send {
     fn try_get_next_job(&mut self) -> Option<(JobId, ZkSyncCircuit)>;
     /// This is a non-blocking function that  yields  some Job for the given circuit type or None
     fn try_get_next_job_by_circuit(&mut self, circuit_id: u8) -> Option<(JobId, ZkSyncCircuit)>;
+    /// Whether a job that has been handed out is cancelled since, polled between proving stages
+    fn is_cancelled(&mut self, _job_id: JobId) -> bool {
+        false
+    }
+    /// Priority of a job that has been handed out
+    fn job_priority(&mut self, _job_id: JobId, _circuit_id: u8) -> JobPriority {
+        0
+    }
+    /// Notified whenever jobs become available, managers without one are polled
+    fn job_availability(&self) -> Option<Arc<JobAvailability>> {
+        None
+    }
+    /// This is a blocking function that gives up after the timeout
+    fn get_next_job_timeout(&mut self, timeout: Duration) -> Option<(JobId, ZkSyncCircuit)> {
+        match self.job_availability() {
+            Some(availability) => {
+                availability.wait_for(Some(timeout), JOB_RECHECK_INTERVAL, || {
+                    self.try_get_next_job()
+                })
+            }
+            None => {
+                let deadline = std::time::Instant::now() + timeout;
+                loop {
+                    if let Some(job) = self.try_get_next_job() {
+                        return Some(job);
+                    }
+                    let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                    if remaining.is_zero() {
+                        return None;
+                    }
+                    std::thread::sleep(remaining.min(JOB_RECHECK_INTERVAL));
+                }
+            }
+        }
+    }
+}
+
+/// Longest a waiting caller sleeps before it looks for jobs again, catches
+/// jobs that were added without a notification
+pub const JOB_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Lets callers sleep until a job manager has new jobs instead of spinning.
+/// Managers call `notify` whenever jobs are added or can be handed out again.
+#[derive(Default)]
+pub struct JobAvailability {
+    generation: Mutex<u64>,
+    condvar: Condvar,
+}
+
+impl JobAvailability {
+    pub fn notify(&self) {
+        *self.generation.lock().unwrap() += 1;
+        self.condvar.notify_all();
+    }
+
+    /// Calls `try_get` until it yields something and waits for a notification
+    /// in between, at most `recheck_interval` at a time. `None` waits forever.
+    pub fn wait_for<T, F: FnMut() -> Option<T>>(
+        &self,
+        timeout: Option<Duration>,
+        recheck_interval: Duration,
+        mut try_get: F,
+    ) -> Option<T> {
+        let deadline = timeout.map(|timeout| std::time::Instant::now() + timeout);
+        loop {
+            // read before trying so that a notification in between isn't missed
+            let generation = *self.generation.lock().unwrap();
+            if let Some(item) = try_get() {
+                return Some(item);
+            }
+            let mut wait = recheck_interval;
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+                if remaining.is_zero() {
+                    return None;
+                }
+                wait = wait.min(remaining);
+            }
+            let guard = self.generation.lock().unwrap();
+            let _ = self
+                .condvar
+                .wait_timeout_while(guard, wait, |current| *current == generation)
+                .unwrap();
+        }
+    }
 }
 
 pub enum Encoding {
@@ -156,8 +317,82 @@ pub trait ArtifactProvider: Send + Sync {
     fn get_vk(&self, circuit_id: u8) -> Result<ZkSyncVerificationKey<Bn256>, Self::ArtifactError>;
 }
 
+/// Flow control state advertised by a prover server to remote synthesizers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credits {
+    /// Number of assemblies the server can take right now
+    pub free_slots: usize,
+    /// Circuit types the server accepts, `None` means all of them
+    pub circuit_ids: Option<Vec<u8>>,
+}
+
+impl Credits {
+    pub fn accepts(&self, circuit_id: u8) -> bool {
+        self.circuit_ids
+            .as_ref()
+            .map_or(true, |circuit_ids| circuit_ids.contains(&circuit_id))
+    }
+}
+
+/// Why a job failed. Only failures of the proving stage can be retried with the
+/// same assembly, synthesis and decoding failures are deterministic and never retried.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FailureClass {
+    /// Proof generation returned an error, typically a GPU fault
+    Proving,
+    /// The generated proof doesn't verify
+    Verification,
+    /// The setup or vk of the circuit couldn't be read, a missing setup is never retried
+    MissingArtifact,
+    /// Synthesis of the circuit panicked
+    Synthesis,
+    /// The assembly encoding is valid but couldn't be decoded
+    Decoding,
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts including the first one
+    pub max_attempts: usize,
+    /// Delay before the first retry, it doubles after every further failed attempt
+    pub backoff: Duration,
+    pub retryable: Vec<FailureClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_secs(1),
+            retryable: vec![FailureClass::Proving, FailureClass::Verification],
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn should_retry(&self, failure_class: FailureClass, attempt: usize) -> bool {
+        attempt < self.max_attempts && self.retryable.contains(&failure_class)
+    }
+
+    /// Delay before the attempt following the given failed one
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1) as u32))
+    }
+}
+
 pub trait RemoteSynthesizer: Send {
     fn try_next(&mut self) -> Option<Box<dyn Read + Send + Sync>>;
+    /// Called by the prover whenever the number of free reusable assemblies changes
+    fn advertise_credits(&mut self, _credits: Credits) {}
+    /// Whether a received job is cancelled since, polled between proving stages
+    fn is_cancelled(&mut self, _job_id: JobId) -> bool {
+        false
+    }
+    /// Priority of a received job
+    fn job_priority(&mut self, _job_id: JobId, _circuit_id: u8) -> JobPriority {
+        0
+    }
 }
 
 pub trait Params: Send + Sync {
@@ -166,4 +401,68 @@ pub trait Params: Send + Sync {
     fn polling_duration(&self) -> Duration {
         Duration::from_millis(1)
     }
+    /// Options used by remote synthesizers to encode assemblies of the given circuit type
+    fn encoding_options(&self, _circuit_id: u8) -> remote_synth::EncodingOptions {
+        remote_synth::EncodingOptions::default()
+    }
+    /// Router that distributes encoded assemblies of remote synthesizers over prover servers
+    fn artifact_router(&self) -> Box<dyn routing::ArtifactRouter> {
+        Box::new(routing::RoundRobinRouter::default())
+    }
+    /// How often remote synthesizers ask idle prover servers for fresh credits
+    fn credits_refresh_interval(&self) -> Duration {
+        Duration::from_millis(100)
+    }
+    /// How many times remote synthesizers resend an assembly that was not acknowledged
+    fn artifact_delivery_retries(&self) -> usize {
+        3
+    }
+    /// Delay before the first resend, it doubles after every further failed attempt
+    fn artifact_delivery_backoff(&self) -> Duration {
+        Duration::from_millis(500)
+    }
+    /// Deadline of a single stage, jobs exceeding it are failed and their resources replaced
+    fn stage_timeout(&self, _stage: JobStage) -> Option<Duration> {
+        None
+    }
+    /// Number of jobs the scheduler takes from the job manager to choose the highest
+    /// priority one from, priorities only matter among these. Jobs in the lookahead
+    /// count as started for the job manager, so it never exceeds the free assemblies.
+    fn scheduler_lookahead(&self) -> usize {
+        16
+    }
+    /// Waiting jobs gain one priority level per interval so that low priorities don't starve
+    fn priority_aging_interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+    /// How many times the scheduler may pass over a job in favour of a job of the
+    /// same priority whose setup is already loaded
+    fn setup_affinity_window(&self) -> usize {
+        8
+    }
+    /// Policy deciding which setups stay resident once every setup slot is taken
+    fn setup_cache_policy(&self) -> Box<dyn setup_cache::SetupCachePolicy> {
+        Box::new(setup_cache::LruPolicy::default())
+    }
+    /// Circuit types whose setups are loaded before the scheduler takes the first job,
+    /// the ones that don't fit into the setup slots are skipped
+    fn preload_circuits(&self) -> Vec<u8> {
+        vec![]
+    }
+    /// Policy for retrying failed proofs inside the service
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+    /// Size of the worker pool of a stage. Every job holds a reusable assembly
+    /// so more workers than parallel synthesis slots would stay idle.
+    fn worker_pool_config(
+        &self,
+        _kind: worker_pool::WorkerPoolKind,
+    ) -> worker_pool::WorkerPoolConfig {
+        let num_parallel_synthesis = self.number_of_parallel_synthesis() as usize;
+        worker_pool::WorkerPoolConfig {
+            num_workers: num_parallel_synthesis,
+            queue_depth: num_parallel_synthesis,
+        }
+    }
 }
@@ -1,6 +1,10 @@
 use std::{
-    io::Write,
-    sync::mpsc::{Receiver, Sender},
+    hash::Hasher,
+    io::{BufReader, Write},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{Receiver, RecvTimeoutError, Sender},
+    },
 };
 
 use prover::ProvingAssembly;
@@ -12,7 +16,11 @@ use zkevm_test_harness::{
     franklin_crypto::plonk::circuit::custom_rescue_gate::Rescue5CustomGate,
 };
 
+use twox_hash::XxHash64;
+
 use super::*;
+use crate::events::{JobContexts, JobEvent, ReportSender};
+use crate::routing::ArtifactRouter;
 use crate::run_prover::{
     recycle_assembly, ThreadGuard, ENCODER_THREAD_HANDLE, SYNTH_THREAD_HANDLE,
 };
@@ -34,45 +42,160 @@ macro_rules! new_vec_with_allocator {
     };
 }
 
-struct SynthesizerContext {
-    encoding_senders: Vec<Sender<(usize, u8, ProvingAssembly)>>,
-    encoding_receivers: Vec<Receiver<(usize, u8, ProvingAssembly)>>,
+pub(crate) struct SynthesizerContext {
+    encoding_senders: Vec<Sender<(usize, u8, EncodingOptions, ProvingAssembly)>>,
+    encoding_receivers: Vec<Receiver<(usize, u8, EncodingOptions, ProvingAssembly)>>,
+    // number of assemblies routed to each sender and not delivered yet
+    encoding_loads: Vec<AtomicUsize>,
+    router: Mutex<Box<dyn ArtifactRouter>>,
+    // latest credits of each sender, `None` if its server doesn't do flow control
+    credits: Vec<Mutex<Option<Credits>>>,
     assembly_sender: Sender<ProvingAssembly>,
     assembly_receiver: Receiver<ProvingAssembly>,
     thread_status_sender: Sender<u8>,
     thread_status_receiver: Receiver<u8>,
-    report_sender: Sender<JobResult>,
-    report_receiver: Receiver<JobResult>,
+    report_sender: ReportSender,
+    report_receiver: Receiver<JobEvent>,
+    job_contexts: Arc<JobContexts>,
 }
 unsafe impl Send for SynthesizerContext {}
 unsafe impl Sync for SynthesizerContext {}
 
 impl SynthesizerContext {
-    fn new(num_senders: usize) -> Self {
+    pub(crate) fn new(num_senders: usize, router: Box<dyn ArtifactRouter>) -> Self {
         let mut encoding_senders = vec![];
         let mut encoding_receivers = vec![];
-        for idx in 0..num_senders {
+        let mut encoding_loads = vec![];
+        let mut credits = vec![];
+        for _ in 0..num_senders {
             let (sender, receiver) = std::sync::mpsc::channel();
             encoding_senders.push(sender);
             encoding_receivers.push(receiver);
+            encoding_loads.push(AtomicUsize::new(0));
+            credits.push(Mutex::new(None));
         }
         let (assembly_sender, assembly_receiver) = std::sync::mpsc::channel();
         let (report_sender, report_receiver) = std::sync::mpsc::channel();
+        let job_contexts = Arc::new(JobContexts::default());
+        let report_sender = ReportSender::new(report_sender, job_contexts.clone());
         let (thread_status_sender, thread_status_receiver) = std::sync::mpsc::channel();
 
         Self {
             encoding_senders,
             encoding_receivers,
+            encoding_loads,
+            router: Mutex::new(router),
+            credits,
             assembly_sender,
             assembly_receiver,
             report_sender,
             report_receiver,
+            job_contexts,
             thread_status_sender,
             thread_status_receiver,
         }
     }
 }
 
+impl SynthesizerContext {
+    /// Asks the router for the sender of the circuit type, `None` if the router has
+    /// no route for it or the chosen server doesn't accept the circuit type.
+    /// Servers without a free slot are reported to the router as fully loaded.
+    pub(crate) fn route(&self, circuit_id: u8) -> Option<usize> {
+        let credits: Vec<_> = self
+            .credits
+            .iter()
+            .map(|credits| credits.lock().unwrap().clone())
+            .collect();
+        let loads: Vec<usize> = self
+            .encoding_loads
+            .iter()
+            .zip(credits.iter())
+            .map(|(load, credits)| {
+                let has_credit = credits.as_ref().map_or(true, |credits| {
+                    credits.free_slots > 0 && credits.accepts(circuit_id)
+                });
+                match has_credit {
+                    true => load.load(Ordering::SeqCst),
+                    false => usize::MAX,
+                }
+            })
+            .collect();
+        let sender_idx = self.router.lock().unwrap().route(circuit_id, &loads)?;
+        match credits.get(sender_idx)? {
+            Some(credits) if !credits.accepts(circuit_id) => None,
+            _ => Some(sender_idx),
+        }
+    }
+
+    /// Takes a credit from the sender and accounts the assembly to its load,
+    /// `false` if the server has no free slot right now
+    pub(crate) fn take_credit(&self, sender_idx: usize) -> bool {
+        let mut credits = self.credits[sender_idx].lock().unwrap();
+        if let Some(credits) = credits.as_mut() {
+            if credits.free_slots == 0 {
+                return false;
+            }
+            credits.free_slots -= 1;
+        }
+        self.encoding_loads[sender_idx].fetch_add(1, Ordering::SeqCst);
+
+        true
+    }
+
+    /// Stores the latest credits of the server, assemblies still queued for it
+    /// are not known to the server yet and keep their credit taken.
+    pub(crate) fn update_credits(&self, sender_idx: usize, credits: Option<Credits>) {
+        let queued = self.encoding_loads[sender_idx].load(Ordering::SeqCst);
+        *self.credits[sender_idx].lock().unwrap() = credits.map(|mut credits| {
+            credits.free_slots = credits.free_slots.saturating_sub(queued);
+            credits
+        });
+    }
+
+    /// Pulls a job only for circuit types that some server still has credit for
+    /// and routes it right away, the credit of the chosen server is taken by the caller.
+    pub(crate) fn try_get_next_job_with_credits<JM: JobManager>(
+        &self,
+        job_manager: &mut JM,
+    ) -> Option<(JobId, ZkSyncCircuit, Option<usize>)> {
+        let mut circuit_ids = vec![];
+        let mut accepts_any = false;
+        for credits in self.credits.iter() {
+            let credits = match credits.lock().unwrap().clone() {
+                Some(credits) => credits,
+                // a server without flow control takes anything
+                None => {
+                    accepts_any = true;
+                    break;
+                }
+            };
+            if credits.free_slots == 0 {
+                continue;
+            }
+            match credits.circuit_ids {
+                Some(accepted_circuit_ids) => circuit_ids.extend(accepted_circuit_ids),
+                None => {
+                    accepts_any = true;
+                    break;
+                }
+            }
+        }
+        let (job_id, circuit) = if accepts_any {
+            job_manager.try_get_next_job()?
+        } else {
+            circuit_ids.sort_unstable();
+            circuit_ids.dedup();
+            circuit_ids
+                .into_iter()
+                .find_map(|circuit_id| job_manager.try_get_next_job_by_circuit(circuit_id))?
+        };
+        let sender_idx = self.route(circuit.numeric_circuit_type());
+
+        Some((job_id, circuit, sender_idx))
+    }
+}
+
 const REMOTE_SYNTH_UTILITY_THREADS: u8 = 2;
 
 pub trait EncodedArtifactSender: Send + Sync {
@@ -81,6 +204,84 @@ pub trait EncodedArtifactSender: Send + Sync {
         encoded_artifact: Box<dyn Read>,
         circuit_id: u8,
     ) -> Result<(), std::io::Error>;
+    /// Same as `send` for an encoding of known length, so that the sender can
+    /// stream it instead of buffering it first.
+    fn send_with_len(
+        &mut self,
+        encoded_artifact: Box<dyn Read>,
+        _len: usize,
+        circuit_id: u8,
+    ) -> Result<(), std::io::Error> {
+        self.send(encoded_artifact, circuit_id)
+    }
+    /// Blocks until the prover side confirmed the encoding handed over by the last `send`.
+    /// Senders which only return from `send` after delivery can keep the default.
+    fn wait_for_acknowledgement(&mut self, _circuit_id: u8) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+    /// Latest credits of the prover server, `None` if it doesn't do flow control.
+    fn credits(&mut self) -> Option<Credits> {
+        None
+    }
+}
+
+/// Reader over an encoding that is kept around for resending after a failed delivery.
+struct SharedEncoding<B> {
+    encoding: Arc<B>,
+    position: usize,
+}
+
+impl<B: AsRef<[u8]>> Read for SharedEncoding<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let encoding: &[u8] = (*self.encoding).as_ref();
+        let remaining = &encoding[self.position..];
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.position += len;
+
+        Ok(len)
+    }
+}
+
+/// Sends the encoding until it is acknowledged, sleeping for `backoff` after the first
+/// failed attempt and doubling it after each next one. Returns the last error once
+/// `max_retries` resends failed too, `on_retry` gets the number and the error of each
+/// failed attempt that is retried.
+/// Delivery is at least once, an encoding whose acknowledgement got lost is sent again.
+/// The prover server drops the encodings of jobs it is working on already.
+pub(crate) fn deliver_encoding<
+    AS: EncodedArtifactSender,
+    B: AsRef<[u8]> + 'static,
+    F: FnMut(usize, &std::io::Error),
+>(
+    artifact_sender: &mut AS,
+    encoding: &Arc<B>,
+    circuit_id: u8,
+    max_retries: usize,
+    mut backoff: Duration,
+    mut on_retry: F,
+) -> Result<(), std::io::Error> {
+    let mut num_retries = 0;
+    loop {
+        let shared_encoding = SharedEncoding {
+            encoding: encoding.clone(),
+            position: 0,
+        };
+        let len = (**encoding).as_ref().len();
+        let result = artifact_sender
+            .send_with_len(Box::new(shared_encoding), len, circuit_id)
+            .and_then(|_| artifact_sender.wait_for_acknowledgement(circuit_id));
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if num_retries < max_retries => {
+                num_retries += 1;
+                on_retry(num_retries, &e);
+                std::thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
 }
 
 pub fn run_remote_synthesizer<
@@ -99,8 +300,10 @@ pub fn run_remote_synthesizer<
             <= num_cpus::get_physical()
     );
     let num_senders = artifact_senders.len();
-    let ctx = SynthesizerContext::new(num_senders);
+    let ctx = SynthesizerContext::new(num_senders, params.artifact_router());
     let ctx = Arc::new(ctx);
+    // routed job whose server had no free slot, it waits for that server
+    let mut waiting_job = None;
 
     // create reusable proving assemblies
     for _ in 0..params.number_of_parallel_synthesis() {
@@ -110,13 +313,19 @@ pub fn run_remote_synthesizer<
     }
 
     // utility thread that encodes assemblies
-    assembly_encoder(ctx.clone(), artifact_senders);
+    assembly_encoder(
+        ctx.clone(),
+        artifact_senders,
+        params.artifact_delivery_retries(),
+        params.artifact_delivery_backoff(),
+        params.credits_refresh_interval(),
+    );
 
     'outer: loop {
         // process collected reports here
         // note: avoid locking for job reporting and use a channel instead
-        for report in ctx.report_receiver.try_iter() {
-            job_reporter.send_report(report);
+        for event in ctx.report_receiver.try_iter() {
+            job_reporter.send_event(event);
         }
 
         // check whether infallible threads are alive or not
@@ -140,14 +349,39 @@ pub fn run_remote_synthesizer<
             continue 'outer;
         };
 
-        let (job_id, circuit) = if let Some(job) = job_manager.try_get_next_job() {
+        let job = match waiting_job.take() {
+            Some(job) => Some(job),
+            None => ctx.try_get_next_job_with_credits(&mut job_manager),
+        };
+        let (job_id, circuit, sender_idx) = if let Some(job) = job {
             job
         } else {
             ctx.assembly_sender.send(assembly).unwrap();
             std::thread::sleep(params.polling_duration());
             continue 'outer;
         };
+        let sender_idx = match sender_idx {
+            Some(sender_idx) if ctx.take_credit(sender_idx) => sender_idx,
+            Some(sender_idx) => {
+                waiting_job = Some((job_id, circuit, Some(sender_idx)));
+                ctx.assembly_sender.send(assembly).unwrap();
+                std::thread::sleep(params.polling_duration());
+                continue 'outer;
+            }
+            None => {
+                let circuit_id = circuit.numeric_circuit_type();
+                ctx.report_sender
+                    .send(JobResult::Failure(
+                        job_id,
+                        format!("no prover server accepts circuit {}", circuit_id),
+                    ))
+                    .unwrap();
+                ctx.assembly_sender.send(assembly).unwrap();
+                continue 'outer;
+            }
+        };
 
+        let encoding_options = params.encoding_options(circuit.numeric_circuit_type());
         let ctx = ctx.clone();
         std::thread::spawn(move || {
             let guard = ThreadGuard::new(
@@ -156,7 +390,15 @@ pub fn run_remote_synthesizer<
                 ctx.report_sender.clone(),
                 ctx.thread_status_sender.clone(),
             );
+            let routed_load = RoutedLoad {
+                ctx: ctx.clone(),
+                sender_idx,
+                is_queued: false,
+            };
             let circuit_id = circuit.numeric_circuit_type();
+            ctx.job_contexts.start(job_id, circuit_id);
+            ctx.job_contexts
+                .enter_stage(job_id, JobStage::Synthesis, None);
             println!("synthesizing circuit {}", circuit.short_description());
 
             let synthesized = std::time::Instant::now();
@@ -165,29 +407,69 @@ pub fn run_remote_synthesizer<
                 .send(JobResult::Synthesized(job_id, synthesized.elapsed()))
                 .unwrap();
 
-            let chunk_size = 20 / ctx.encoding_senders.len();
-            let sender_idx = circuit_id as usize / chunk_size;
-
-            if let Err(e) = ctx.encoding_senders[sender_idx].send((job_id, circuit_id, assembly)) {
-                ctx.report_sender
-                    .send(JobResult::Failure(
-                        job_id,
-                        format!("encoder handler failed: {}", e),
-                    ))
-                    .unwrap();
-            };
+            match ctx.encoding_senders[sender_idx].send((
+                job_id,
+                circuit_id,
+                encoding_options,
+                assembly,
+            )) {
+                Ok(()) => routed_load.queued(),
+                Err(e) => {
+                    ctx.report_sender
+                        .send(JobResult::Failure(
+                            job_id,
+                            format!("encoder handler failed: {}", e),
+                        ))
+                        .unwrap();
+                    ctx.job_contexts.finish(job_id);
+                }
+            }
         });
     }
 }
 
+/// Load a routed assembly puts on its sender until the encoder takes it over,
+/// released if synthesis fails before the assembly is queued
+struct RoutedLoad {
+    ctx: Arc<SynthesizerContext>,
+    sender_idx: usize,
+    is_queued: bool,
+}
+
+impl RoutedLoad {
+    fn queued(mut self) {
+        self.is_queued = true;
+    }
+}
+
+impl Drop for RoutedLoad {
+    fn drop(&mut self) {
+        if !self.is_queued {
+            self.ctx.encoding_loads[self.sender_idx].fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
 fn assembly_encoder<AS: EncodedArtifactSender + 'static>(
     ctx: Arc<SynthesizerContext>,
-    mut artifact_senders: Vec<AS>,
+    artifact_senders: Vec<AS>,
+    max_retries: usize,
+    backoff: Duration,
+    credits_refresh_interval: Duration,
 ) {
     for (sender_idx, mut artifact_sender) in artifact_senders.into_iter().enumerate() {
         let ctx = ctx.clone();
         std::thread::spawn(move || loop {
-            let (job_id, circuit_id, assembly) = ctx.encoding_receivers[sender_idx].recv().unwrap();
+            // refresh credits while idle so that the scheduler learns about freed slots
+            let (job_id, circuit_id, encoding_options, assembly) =
+                match ctx.encoding_receivers[sender_idx].recv_timeout(credits_refresh_interval) {
+                    Ok(job) => job,
+                    Err(RecvTimeoutError::Timeout) => {
+                        ctx.update_credits(sender_idx, artifact_sender.credits());
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                };
             let guard = ThreadGuard::new(
                 ENCODER_THREAD_HANDLE,
                 job_id,
@@ -198,12 +480,17 @@ fn assembly_encoder<AS: EncodedArtifactSender + 'static>(
 
             let start = std::time::Instant::now();
             let mut buffer = new_vec_with_allocator!(capacity);
-            serialize_job(&assembly, job_id, circuit_id, &mut buffer);
+            serialize_job_with_options(
+                &assembly,
+                job_id,
+                circuit_id,
+                &encoding_options,
+                &mut buffer,
+            );
             let recycled_assembly = recycle_assembly(assembly);
             ctx.assembly_sender.send(recycled_assembly).unwrap();
 
-            let assembly_encoding = std::io::Cursor::new(buffer);
-            let assembly_encoding = Box::new(assembly_encoding);
+            let assembly_encoding = Arc::new(buffer);
 
             let assembly_encoded = start.elapsed();
             ctx.report_sender
@@ -211,63 +498,572 @@ fn assembly_encoder<AS: EncodedArtifactSender + 'static>(
                 .unwrap();
 
             let assembly_transferred = std::time::Instant::now();
-            artifact_sender.send(assembly_encoding, circuit_id).unwrap();
-            ctx.report_sender
-                .send(JobResult::AssemblyTransferred(
+            let report = match deliver_encoding(
+                &mut artifact_sender,
+                &assembly_encoding,
+                circuit_id,
+                max_retries,
+                backoff,
+                |attempt, e| {
+                    ctx.report_sender
+                        .send(JobResult::RetryScheduled(
+                            job_id,
+                            attempt,
+                            format!("assembly delivery failed: {}", e),
+                        ))
+                        .unwrap()
+                },
+            ) {
+                Ok(()) => JobResult::AssemblyTransferred(job_id, assembly_transferred.elapsed()),
+                Err(e) => JobResult::Failure(
                     job_id,
-                    assembly_transferred.elapsed(),
-                ))
-                .unwrap();
+                    format!(
+                        "assembly delivery failed after {} retries: {}",
+                        max_retries, e
+                    ),
+                ),
+            };
+            ctx.encoding_loads[sender_idx].fetch_sub(1, Ordering::SeqCst);
+            ctx.update_credits(sender_idx, artifact_sender.credits());
+            ctx.report_sender.send(report).unwrap();
+            ctx.job_contexts.finish(job_id);
         });
     }
 }
 
+/// Magic bytes every assembly encoding starts with.
+pub const ENCODING_MAGIC: [u8; 4] = *b"ZKPA";
+/// Version of the assembly wire format, bump it on every incompatible layout change.
+pub const ENCODING_FORMAT_VERSION: u16 = 4;
+/// Identifier of the scalar field (Bn256 `Fr`) the assignments are encoded over.
+pub const BN256_FIELD_ID: u8 = 1;
+
+const NUM_SECTIONS: usize = 3;
+// that is huge, use it until proper measurement
+const MAX_TABLE_NAME_ENCODING_LEN: usize = 1 << 16;
+const MAX_CANONICAL_TABLE_ROWS: usize = 327680;
+const ENCODING_HEADER_SIZE: usize = 4 + 2 + 1 + 1 + 1 + 1 + NUM_SECTIONS * 8;
+const ZSTD_COMPRESSION_LEVEL: i32 = 1;
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(std::io::Error),
+    Truncated,
+    MisalignedLength {
+        len: usize,
+        unit_len: usize,
+    },
+    LengthMismatch {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    CapacityOverflow {
+        requested: usize,
+        capacity: usize,
+    },
+    InvalidTableName(String),
+    DuplicateTable(String),
+    AssemblyNotEmpty,
+    InvalidMagic([u8; 4]),
+    UnsupportedVersion(u16),
+    DomainSizeMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    FieldMismatch {
+        expected: u8,
+        actual: u8,
+    },
+    UnsupportedCompression(u8),
+    UnsupportedColumnEncoding(u8),
+    SectionLengthMismatch {
+        section: Section,
+        declared: u64,
+        actual: u64,
+    },
+    SectionChecksumMismatch {
+        section: Section,
+        expected: u64,
+        actual: u64,
+    },
+    PayloadChecksumMismatch {
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl DecodeError {
+    /// Whether the payload arrived damaged, as opposed to being well-formed but incompatible.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Self::Truncated
+                | Self::SectionChecksumMismatch { .. }
+                | Self::PayloadChecksumMismatch { .. }
+        )
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => Self::Truncated,
+            _ => Self::Io(e),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Section {
+    Assignments = 0,
+    Variables = 1,
+    Tables = 2,
+}
+
+/// Self-describing header written in front of every assembly encoding so that
+/// synthesizers and provers built from different commits refuse each other's payloads.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodingHeader {
+    pub version: u16,
+    pub domain_size_log: u8,
+    pub field_id: u8,
+    pub compression: Compression,
+    pub column_encoding: ColumnEncoding,
+    /// Lengths of the uncompressed sections.
+    pub section_lengths: [u64; NUM_SECTIONS],
+}
+
+impl EncodingHeader {
+    pub fn for_assembly(assembly: &ProvingAssembly, options: &EncodingOptions) -> Self {
+        let mut section_lengths = [0u64; NUM_SECTIONS];
+        section_lengths[Section::Assignments as usize] =
+            assignments_len(assembly, options.column_encoding);
+        section_lengths[Section::Variables as usize] =
+            variables_len(assembly, options.column_encoding);
+        section_lengths[Section::Tables as usize] = tables_len(assembly);
+
+        Self {
+            version: ENCODING_FORMAT_VERSION,
+            domain_size_log: Prover::get_max_domain_size_log() as u8,
+            field_id: BN256_FIELD_ID,
+            compression: options.compression,
+            column_encoding: options.column_encoding,
+            section_lengths,
+        }
+    }
+
+    pub fn write<W: Write>(&self, buffer: &mut W) -> std::io::Result<()> {
+        let mut encoding = Vec::with_capacity(ENCODING_HEADER_SIZE);
+        encoding.extend_from_slice(&ENCODING_MAGIC);
+        encoding.extend_from_slice(&self.version.to_le_bytes());
+        encoding.push(self.domain_size_log);
+        encoding.push(self.field_id);
+        encoding.push(self.compression as u8);
+        encoding.push(self.column_encoding as u8);
+        for len in self.section_lengths.iter() {
+            encoding.extend_from_slice(&len.to_le_bytes());
+        }
+        assert_eq!(encoding.len(), ENCODING_HEADER_SIZE);
+
+        buffer.write_all(&encoding)
+    }
+
+    /// Reads the header and checks that the payload was produced for this prover build.
+    pub fn read<R: Read>(encoding: &mut R) -> Result<Self, DecodeError> {
+        let mut magic = [0u8; 4];
+        encoding.read_exact(&mut magic)?;
+        if magic != ENCODING_MAGIC {
+            return Err(DecodeError::InvalidMagic(magic));
+        }
+
+        let mut version = [0u8; 2];
+        encoding.read_exact(&mut version)?;
+        let version = u16::from_le_bytes(version);
+        if version != ENCODING_FORMAT_VERSION {
+            return Err(DecodeError::UnsupportedVersion(version));
+        }
+
+        let mut params = [0u8; 4];
+        encoding.read_exact(&mut params)?;
+        let [domain_size_log, field_id, compression, column_encoding] = params;
+        let expected_domain_size_log = Prover::get_max_domain_size_log() as u8;
+        if domain_size_log != expected_domain_size_log {
+            return Err(DecodeError::DomainSizeMismatch {
+                expected: expected_domain_size_log,
+                actual: domain_size_log,
+            });
+        }
+        if field_id != BN256_FIELD_ID {
+            return Err(DecodeError::FieldMismatch {
+                expected: BN256_FIELD_ID,
+                actual: field_id,
+            });
+        }
+        let compression = Compression::from_u8(compression)
+            .ok_or(DecodeError::UnsupportedCompression(compression))?;
+        let column_encoding = ColumnEncoding::from_u8(column_encoding)
+            .ok_or(DecodeError::UnsupportedColumnEncoding(column_encoding))?;
+
+        let mut section_lengths = [0u64; NUM_SECTIONS];
+        for len in section_lengths.iter_mut() {
+            let mut len_bytes = [0u8; 8];
+            encoding.read_exact(&mut len_bytes)?;
+            *len = u64::from_le_bytes(len_bytes);
+        }
+
+        Ok(Self {
+            version,
+            domain_size_log,
+            field_id,
+            compression,
+            column_encoding,
+            section_lengths,
+        })
+    }
+
+    /// Checks the length of a decoded section and passes its checksum through.
+    fn check_section_len(
+        &self,
+        section: Section,
+        (actual, checksum): (u64, u64),
+    ) -> Result<u64, DecodeError> {
+        let declared = self.section_lengths[section as usize];
+        if declared != actual {
+            return Err(DecodeError::SectionLengthMismatch {
+                section,
+                declared,
+                actual,
+            });
+        }
+
+        Ok(checksum)
+    }
+}
+
+/// Compression applied to the sections and the trailer, header and job ids always
+/// stay uncompressed so that they can be read before picking a decoder.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Compression {
+    #[default]
+    None = 0,
+    Zstd = 1,
+    Lz4 = 2,
+}
+
+impl Compression {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::None),
+            1 => Some(Self::Zstd),
+            2 => Some(Self::Lz4),
+            _ => None,
+        }
+    }
+}
+
+/// Layout of aux assignments and variable columns, which are mostly padding.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ColumnEncoding {
+    /// Raw elements as they are laid out in memory
+    #[default]
+    Dense = 0,
+    /// Runs of byte-identical elements collapsed into (run length, element) pairs
+    RunLength = 1,
+}
+
+impl ColumnEncoding {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Dense),
+            1 => Some(Self::RunLength),
+            _ => None,
+        }
+    }
+}
+
+/// Encoding knobs chosen by the synthesizer per job, the prover learns them from the header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EncodingOptions {
+    pub compression: Compression,
+    pub column_encoding: ColumnEncoding,
+}
+
+enum PayloadWriter<W: Write> {
+    Plain(W),
+    Zstd(zstd::stream::write::Encoder<'static, W>),
+    Lz4(lz4_flex::frame::FrameEncoder<W>),
+}
+
+impl<W: Write> PayloadWriter<W> {
+    fn compress(self, compression: Compression) -> std::io::Result<Self> {
+        let inner = self.finish()?;
+        let writer = match compression {
+            Compression::None => Self::Plain(inner),
+            Compression::Zstd => Self::Zstd(zstd::stream::write::Encoder::new(
+                inner,
+                ZSTD_COMPRESSION_LEVEL,
+            )?),
+            Compression::Lz4 => Self::Lz4(lz4_flex::frame::FrameEncoder::new(inner)),
+        };
+
+        Ok(writer)
+    }
+
+    fn finish(self) -> std::io::Result<W> {
+        match self {
+            Self::Plain(inner) => Ok(inner),
+            Self::Zstd(encoder) => encoder.finish(),
+            Self::Lz4(encoder) => encoder
+                .finish()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl<W: Write> Write for PayloadWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(inner) => inner.write(buf),
+            Self::Zstd(encoder) => encoder.write(buf),
+            Self::Lz4(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Plain(inner) => inner.flush(),
+            Self::Zstd(encoder) => encoder.flush(),
+            Self::Lz4(encoder) => encoder.flush(),
+        }
+    }
+}
+
+enum PayloadReader<R: Read> {
+    Plain(R),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<R>>),
+    Lz4(lz4_flex::frame::FrameDecoder<R>),
+}
+
+impl<R: Read> PayloadReader<R> {
+    fn decompress(self, compression: Compression) -> std::io::Result<Self> {
+        let inner = match self {
+            Self::Plain(inner) => inner,
+            // payload is compressed at most once
+            _ => unreachable!(),
+        };
+        let reader = match compression {
+            Compression::None => Self::Plain(inner),
+            Compression::Zstd => Self::Zstd(zstd::stream::read::Decoder::new(inner)?),
+            Compression::Lz4 => Self::Lz4(lz4_flex::frame::FrameDecoder::new(inner)),
+        };
+
+        Ok(reader)
+    }
+}
+
+impl<R: Read> Read for PayloadReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Plain(inner) => inner.read(buf),
+            Self::Zstd(decoder) => decoder.read(buf),
+            Self::Lz4(decoder) => decoder.read(buf),
+        }
+    }
+}
+
+/// Writer that keeps running checksums over the whole uncompressed payload and the current
+/// section. Compression is switched on in the middle of the stream, right after the header.
+struct ChecksumWriter<W: Write> {
+    inner: Option<PayloadWriter<W>>,
+    payload: XxHash64,
+    section: XxHash64,
+    section_checksums: [u64; NUM_SECTIONS],
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner: Some(PayloadWriter::Plain(inner)),
+            payload: XxHash64::with_seed(0),
+            section: XxHash64::with_seed(0),
+            section_checksums: [0u64; NUM_SECTIONS],
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut PayloadWriter<W> {
+        self.inner.as_mut().expect("payload writer")
+    }
+
+    fn start_compression(&mut self, compression: Compression) -> std::io::Result<()> {
+        let inner = self.inner.take().expect("payload writer");
+        self.inner = Some(inner.compress(compression)?);
+        Ok(())
+    }
+
+    fn start_section(&mut self) {
+        self.section = XxHash64::with_seed(0);
+    }
+
+    fn end_section(&mut self, section: Section) {
+        let hasher = std::mem::replace(&mut self.section, XxHash64::with_seed(0));
+        self.section_checksums[section as usize] = hasher.finish();
+    }
+
+    /// Writes the trailer with section and payload checksums, trailer itself isn't covered.
+    fn finish(mut self) -> std::io::Result<W> {
+        let section_checksums = self.section_checksums;
+        let payload_checksum = self.payload.finish();
+        let inner = self.inner_mut();
+        for checksum in section_checksums.iter() {
+            inner.write_all(&checksum.to_le_bytes())?;
+        }
+        inner.write_all(&payload_checksum.to_le_bytes())?;
+
+        self.inner.take().expect("payload writer").finish()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner_mut().write(buf)?;
+        self.payload.write(&buf[..n]);
+        self.section.write(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner_mut().flush()
+    }
+}
+
+/// Reader counterpart of `ChecksumWriter`, it also counts bytes of the current section
+/// so that declared section lengths can be verified.
+pub struct ChecksumReader<R: Read> {
+    inner: Option<PayloadReader<R>>,
+    payload: XxHash64,
+    section: XxHash64,
+    section_len: u64,
+}
+
+impl<R: Read> ChecksumReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner: Some(PayloadReader::Plain(inner)),
+            payload: XxHash64::with_seed(0),
+            section: XxHash64::with_seed(0),
+            section_len: 0,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut PayloadReader<R> {
+        self.inner.as_mut().expect("payload reader")
+    }
+
+    fn start_decompression(&mut self, compression: Compression) -> Result<(), DecodeError> {
+        let inner = self.inner.take().expect("payload reader");
+        self.inner = Some(inner.decompress(compression)?);
+        Ok(())
+    }
+
+    fn start_section(&mut self) {
+        self.section = XxHash64::with_seed(0);
+        self.section_len = 0;
+    }
+
+    fn end_section(&mut self) -> (u64, u64) {
+        let hasher = std::mem::replace(&mut self.section, XxHash64::with_seed(0));
+        (std::mem::take(&mut self.section_len), hasher.finish())
+    }
+
+    fn read_trailer(&mut self) -> Result<([u64; NUM_SECTIONS], u64), DecodeError> {
+        let inner = self.inner_mut();
+        let mut section_checksums = [0u64; NUM_SECTIONS];
+        for checksum in section_checksums.iter_mut() {
+            let mut bytes = [0u8; 8];
+            inner.read_exact(&mut bytes)?;
+            *checksum = u64::from_le_bytes(bytes);
+        }
+        let mut bytes = [0u8; 8];
+        inner.read_exact(&mut bytes)?;
+
+        Ok((section_checksums, u64::from_le_bytes(bytes)))
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner_mut().read(buf)?;
+        self.payload.write(&buf[..n]);
+        self.section.write(&buf[..n]);
+        self.section_len += n as u64;
+        Ok(n)
+    }
+}
+
 pub fn serialize_job<W: Write>(
     assembly: &ProvingAssembly,
     job_id: usize,
     circuit_id: u8,
     buffer: &mut W,
 ) {
+    serialize_job_with_options(
+        assembly,
+        job_id,
+        circuit_id,
+        &EncodingOptions::default(),
+        buffer,
+    )
+}
+
+/// Encodes the job straight into `buffer`, compression is streamed so no intermediate
+/// buffer is needed.
+pub fn serialize_job_with_options<W: Write>(
+    assembly: &ProvingAssembly,
+    job_id: usize,
+    circuit_id: u8,
+    options: &EncodingOptions,
+    buffer: &mut W,
+) {
+    let header = EncodingHeader::for_assembly(assembly, options);
+    let mut buffer = ChecksumWriter::new(buffer);
+    header.write(&mut buffer).unwrap();
     buffer.write_all(&job_id.to_le_bytes()).unwrap();
-    buffer.write(&[circuit_id]).unwrap();
-    custom_assembly_serialization(assembly, buffer);
+    buffer.write_all(&[circuit_id]).unwrap();
+    serialize_sections(&header, assembly, &mut buffer);
+    buffer.finish().unwrap();
 }
 
-fn deserialize_job_with_guard<R: Read>(
-    buffer: &mut R,
-    assembly: &mut ProvingAssembly,
-    ctx: Arc<super::run_prover::ProverContext>,
-) -> (usize, u8) {
-    add_gates_into_assembly(assembly);
+/// Reads and validates the encoding header followed by the job id and circuit id.
+/// The remaining payload can be decoded with `deserialize_sections` using the same reader.
+pub fn deserialize_job_header<R: Read>(
+    buffer: &mut ChecksumReader<R>,
+) -> Result<(EncodingHeader, usize, u8), DecodeError> {
+    let header = EncodingHeader::read(buffer)?;
 
     let mut job_id_bytes = [0u8; 8];
-    buffer.read_exact(&mut job_id_bytes[..]).unwrap();
+    buffer.read_exact(&mut job_id_bytes[..])?;
     let job_id = usize::from_le_bytes(job_id_bytes);
-    let guard = ThreadGuard::new(
-        ENCODER_THREAD_HANDLE,
-        job_id,
-        ctx.report_sender.clone(),
-        ctx.thread_status_sender.clone(),
-    );
     let mut circuit_id = [0u8; 1];
-    buffer.read_exact(&mut circuit_id[..]).unwrap();
+    buffer.read_exact(&mut circuit_id[..])?;
     let circuit_id = circuit_id[0];
-    custom_assembly_deserialization(buffer, assembly);
 
-    (job_id, circuit_id)
+    Ok((header, job_id, circuit_id))
 }
-pub fn deserialize_job<R: Read>(buffer: &mut R, assembly: &mut ProvingAssembly) -> (usize, u8) {
+
+pub fn deserialize_job<R: Read>(
+    buffer: &mut R,
+    assembly: &mut ProvingAssembly,
+) -> Result<(usize, u8), DecodeError> {
     add_gates_into_assembly(assembly);
 
-    let mut job_id_bytes = [0u8; 8];
-    buffer.read_exact(&mut job_id_bytes[..]).unwrap();
-    let job_id = usize::from_le_bytes(job_id_bytes);
-    let mut circuit_id = [0u8; 1];
-    buffer.read_exact(&mut circuit_id[..]).unwrap();
-    let circuit_id = circuit_id[0];
-    custom_assembly_deserialization(buffer, assembly);
+    let mut buffer = ChecksumReader::new(buffer);
+    let (header, job_id, circuit_id) = deserialize_job_header(&mut buffer)?;
+    deserialize_sections(&header, &mut buffer, assembly)?;
 
-    (job_id, circuit_id)
+    Ok((job_id, circuit_id))
 }
 
 fn add_gates_into_assembly(assembly: &mut ProvingAssembly) {
@@ -292,12 +1088,12 @@ pub fn calculate_serialization_capacity_for_proving_assembly() -> usize {
         .fold(0, |acc, x| acc + x);
 
     // table names
-    let num_bytes_for_single_table_name = 1 << 16; // that is huge, use it until proper measurement
+    let num_bytes_for_single_table_name = MAX_TABLE_NAME_ENCODING_LEN;
     let max_num_tables = Prover::get_num_lookup_tables();
     capacity += max_num_tables * num_bytes_for_single_table_name;
 
     // canonical tables
-    let num_bytes_for_single_canonical_table = 327680 * 3 * 32;
+    let num_bytes_for_single_canonical_table = MAX_CANONICAL_TABLE_ROWS * 3 * 32;
     capacity += max_num_tables * num_bytes_for_single_canonical_table;
 
     // table indexes
@@ -308,18 +1104,96 @@ pub fn calculate_serialization_capacity_for_proving_assembly() -> usize {
 }
 
 pub fn custom_assembly_serialization<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
-    serialize_assignments(assembly, buffer);
-    serialize_variables(assembly, buffer);
+    custom_assembly_serialization_with_options(assembly, &EncodingOptions::default(), buffer)
+}
+
+pub fn custom_assembly_serialization_with_options<W: Write>(
+    assembly: &ProvingAssembly,
+    options: &EncodingOptions,
+    buffer: &mut W,
+) {
+    let header = EncodingHeader::for_assembly(assembly, options);
+    let mut buffer = ChecksumWriter::new(buffer);
+    header.write(&mut buffer).unwrap();
+    serialize_sections(&header, assembly, &mut buffer);
+    buffer.finish().unwrap();
+}
+
+pub fn custom_assembly_deserialization<R: Read>(
+    encoding: &mut R,
+    assembly: &mut ProvingAssembly,
+) -> Result<(), DecodeError> {
+    let mut encoding = ChecksumReader::new(encoding);
+    let header = EncodingHeader::read(&mut encoding)?;
+    deserialize_sections(&header, &mut encoding, assembly)
+}
+
+fn serialize_sections<W: Write>(
+    header: &EncodingHeader,
+    assembly: &ProvingAssembly,
+    buffer: &mut ChecksumWriter<W>,
+) {
+    buffer.start_compression(header.compression).unwrap();
+    // header and job ids are only covered by the payload checksum
+    buffer.start_section();
+    serialize_assignments(assembly, header.column_encoding, buffer);
+    buffer.end_section(Section::Assignments);
+    serialize_variables(assembly, header.column_encoding, buffer);
+    buffer.end_section(Section::Variables);
     serialize_tables(assembly, buffer);
+    buffer.end_section(Section::Tables);
 }
 
-pub fn custom_assembly_deserialization<R: Read>(encoding: &mut R, assembly: &mut ProvingAssembly) {
-    deserialize_assignments(encoding, assembly);
-    deserialize_variables(encoding, assembly);
-    deserialize_tables(encoding, assembly);
+/// Decodes assignments, variables and tables of an assembly whose header has already been
+/// read from the same reader, then verifies section and payload checksums.
+pub fn deserialize_sections<R: Read>(
+    header: &EncodingHeader,
+    encoding: &mut ChecksumReader<R>,
+    assembly: &mut ProvingAssembly,
+) -> Result<(), DecodeError> {
+    let mut section_checksums = [0u64; NUM_SECTIONS];
+
+    encoding.start_decompression(header.compression)?;
+    encoding.start_section();
+    deserialize_assignments(encoding, header.column_encoding, assembly)?;
+    section_checksums[Section::Assignments as usize] =
+        header.check_section_len(Section::Assignments, encoding.end_section())?;
+    deserialize_variables(encoding, header.column_encoding, assembly)?;
+    section_checksums[Section::Variables as usize] =
+        header.check_section_len(Section::Variables, encoding.end_section())?;
+    deserialize_tables(encoding, assembly)?;
+    section_checksums[Section::Tables as usize] =
+        header.check_section_len(Section::Tables, encoding.end_section())?;
+
+    let payload_checksum = encoding.payload.finish();
+    let (expected_section_checksums, expected_payload_checksum) = encoding.read_trailer()?;
+    for (idx, section) in [Section::Assignments, Section::Variables, Section::Tables]
+        .into_iter()
+        .enumerate()
+    {
+        if section_checksums[idx] != expected_section_checksums[idx] {
+            return Err(DecodeError::SectionChecksumMismatch {
+                section,
+                expected: expected_section_checksums[idx],
+                actual: section_checksums[idx],
+            });
+        }
+    }
+    if payload_checksum != expected_payload_checksum {
+        return Err(DecodeError::PayloadChecksumMismatch {
+            expected: expected_payload_checksum,
+            actual: payload_checksum,
+        });
+    }
+
+    Ok(())
 }
 
-fn serialize_assignments<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
+fn serialize_assignments<W: Write>(
+    assembly: &ProvingAssembly,
+    column_encoding: ColumnEncoding,
+    buffer: &mut W,
+) {
     let num_aux_gates = assembly.num_aux_gates.to_le_bytes();
     serialize_generic(&num_aux_gates, buffer);
     let num_input_gates = assembly.num_input_gates.to_le_bytes();
@@ -329,43 +1203,98 @@ fn serialize_assignments<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
     let num_aux = assembly.num_aux.to_le_bytes();
     serialize_generic(&num_aux, buffer);
     serialize_generic(&assembly.input_assingments, buffer);
-    serialize_generic(&assembly.aux_assingments, buffer);
+    serialize_column(&assembly.aux_assingments, column_encoding, buffer);
 }
 
-fn deserialize_assignments<R: Read>(encoding: &mut R, assembly: &mut ProvingAssembly) {
-    let mut num_aux_gates = vec![0u8; 8];
-    deserialize_generic(encoding, &mut num_aux_gates);
-    assembly.num_aux_gates = usize::from_le_bytes(num_aux_gates.try_into().unwrap());
-    let mut num_input_gates = vec![0u8; 8];
-    deserialize_generic(encoding, &mut num_input_gates);
-    assembly.num_input_gates = usize::from_le_bytes(num_input_gates.try_into().unwrap());
-    let mut num_inputs = vec![0u8; 8];
-    deserialize_generic(encoding, &mut num_inputs);
-    assembly.num_inputs = usize::from_le_bytes(num_inputs.try_into().unwrap());
-    let mut num_aux = vec![0u8; 8];
-    deserialize_generic(encoding, &mut num_aux);
-    assembly.num_aux = usize::from_le_bytes(num_aux.try_into().unwrap());
+/// Encoded length of `serialize_assignments`
+fn assignments_len(assembly: &ProvingAssembly, column_encoding: ColumnEncoding) -> u64 {
+    // gate and input counts
+    4 * generic_len(&[0u8; 8])
+        + generic_len(&assembly.input_assingments)
+        + column_len(&assembly.aux_assingments, column_encoding)
+}
+
+fn deserialize_assignments<R: Read>(
+    encoding: &mut R,
+    column_encoding: ColumnEncoding,
+    assembly: &mut ProvingAssembly,
+) -> Result<(), DecodeError> {
+    assembly.num_aux_gates = deserialize_usize(encoding)?;
+    assembly.num_input_gates = deserialize_usize(encoding)?;
+    assembly.num_inputs = deserialize_usize(encoding)?;
+    assembly.num_aux = deserialize_usize(encoding)?;
+
+    deserialize_generic(
+        encoding,
+        &mut assembly.input_assingments,
+        Prover::get_max_domain_size(),
+    )?;
+    if assembly.num_inputs != assembly.num_input_gates {
+        return Err(DecodeError::LengthMismatch {
+            field: "num_inputs",
+            expected: assembly.num_input_gates,
+            actual: assembly.num_inputs,
+        });
+    }
 
-    deserialize_generic(encoding, &mut assembly.input_assingments);
-    assert_eq!(assembly.num_inputs, assembly.num_input_gates);
+    deserialize_column(
+        encoding,
+        column_encoding,
+        &mut assembly.aux_assingments,
+        Prover::get_max_num_variables(),
+    )
+}
 
-    deserialize_generic(encoding, &mut assembly.aux_assingments);
+fn deserialize_usize<R: Read>(encoding: &mut R) -> Result<usize, DecodeError> {
+    let mut value = vec![0u8; 8];
+    deserialize_generic(encoding, &mut value, 8)?;
+    let value: [u8; 8] =
+        value
+            .try_into()
+            .map_err(|value: Vec<u8>| DecodeError::LengthMismatch {
+                field: "usize",
+                expected: 8,
+                actual: value.len(),
+            })?;
+
+    Ok(usize::from_le_bytes(value))
 }
 
-fn serialize_variables<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
+fn serialize_variables<W: Write>(
+    assembly: &ProvingAssembly,
+    column_encoding: ColumnEncoding,
+    buffer: &mut W,
+) {
     for idx in 0..4 {
         let poly_idx = PolyIdentifier::VariablesPolynomial(idx);
         let variables = assembly.aux_storage.state_map.get(&poly_idx).unwrap();
-        serialize_generic(variables, buffer);
+        serialize_column(variables, column_encoding, buffer);
     }
     for idx in 0..4 {
         let poly_idx = PolyIdentifier::VariablesPolynomial(idx);
         let variables = assembly.inputs_storage.state_map.get(&poly_idx).unwrap();
-        serialize_generic(variables, buffer);
+        serialize_column(variables, column_encoding, buffer);
     }
 }
 
-fn deserialize_variables<R: Read>(encoding: &mut R, assembly: &mut ProvingAssembly) {
+/// Encoded length of `serialize_variables`
+fn variables_len(assembly: &ProvingAssembly, column_encoding: ColumnEncoding) -> u64 {
+    [&assembly.aux_storage, &assembly.inputs_storage]
+        .into_iter()
+        .flat_map(|storage| {
+            (0..4).map(move |idx| {
+                let poly_idx = PolyIdentifier::VariablesPolynomial(idx);
+                column_len(storage.state_map.get(&poly_idx).unwrap(), column_encoding)
+            })
+        })
+        .sum()
+}
+
+fn deserialize_variables<R: Read>(
+    encoding: &mut R,
+    column_encoding: ColumnEncoding,
+    assembly: &mut ProvingAssembly,
+) -> Result<(), DecodeError> {
     for idx in 0..4 {
         let idx = PolyIdentifier::VariablesPolynomial(idx);
         let variables = assembly
@@ -373,7 +1302,12 @@ fn deserialize_variables<R: Read>(encoding: &mut R, assembly: &mut ProvingAssemb
             .state_map
             .entry(idx)
             .or_insert(new_vec_with_allocator!(0));
-        deserialize_generic(encoding, variables);
+        deserialize_column(
+            encoding,
+            column_encoding,
+            variables,
+            Prover::get_max_domain_size(),
+        )?;
     }
     for idx in 0..4 {
         let idx = PolyIdentifier::VariablesPolynomial(idx);
@@ -382,18 +1316,25 @@ fn deserialize_variables<R: Read>(encoding: &mut R, assembly: &mut ProvingAssemb
             .state_map
             .entry(idx)
             .or_insert(new_vec_with_allocator!(0));
-        deserialize_generic(encoding, variables);
+        deserialize_column(
+            encoding,
+            column_encoding,
+            variables,
+            Prover::get_max_domain_size(),
+        )?;
     }
+
+    Ok(())
 }
 
 fn serialize_tables<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
     let table_names = assembly.known_table_names.clone();
 
     let num_tables = table_names.len();
+    buffer.write_all(&num_tables.to_le_bytes()).unwrap();
     if num_tables == 0 {
         return;
     }
-    buffer.write(&num_tables.to_le_bytes()).unwrap();
 
     for table_name in table_names.iter() {
         let mut encoding = bincode::serialize(&table_name).unwrap();
@@ -423,50 +1364,104 @@ fn serialize_tables<W: Write>(assembly: &ProvingAssembly, buffer: &mut W) {
     }
 }
 
-fn deserialize_tables<R: Read>(encoding: &mut R, assembly: &mut ProvingAssembly) {
+/// Encoded length of `serialize_tables`
+fn tables_len(assembly: &ProvingAssembly) -> u64 {
+    let table_names = &assembly.known_table_names;
+    // number of tables
+    let mut len = 8;
+    if table_names.is_empty() {
+        return len;
+    }
+    for table_name in table_names.iter() {
+        len += 8 + bincode::serialized_size(&table_name).unwrap();
+    }
+    let table_ids_len: usize = table_names
+        .iter()
+        .map(|table_name| std::mem::size_of_val(assembly.known_table_ids.get(table_name).unwrap()))
+        .sum();
+    len += 8 + table_ids_len as u64;
+    for table_name in table_names.iter() {
+        let table = assembly
+            .individual_table_canonical_sorted_entries
+            .get(table_name)
+            .unwrap();
+        len += generic_len(table);
+    }
+    for table_name in table_names.iter() {
+        len += generic_len(assembly.individual_table_entries.get(table_name).unwrap());
+    }
+
+    len
+}
+
+fn deserialize_tables<R: Read>(
+    encoding: &mut R,
+    assembly: &mut ProvingAssembly,
+) -> Result<(), DecodeError> {
     let ProvingAssembly {
         individual_table_canonical_sorted_entries,
         individual_table_entries,
         reusable_buffer_for_lookup_entries,
         ..
     } = assembly;
-    assert!(individual_table_canonical_sorted_entries.is_empty());
-    assert!(individual_table_entries.is_empty());
+    if !individual_table_canonical_sorted_entries.is_empty() || !individual_table_entries.is_empty()
+    {
+        return Err(DecodeError::AssemblyNotEmpty);
+    }
 
     let mut num_tables_as_bytes = [0u8; 8];
-    encoding.read(&mut num_tables_as_bytes[..]).unwrap();
+    encoding.read_exact(&mut num_tables_as_bytes[..])?;
     let num_tables = usize::from_le_bytes(num_tables_as_bytes);
 
     if num_tables == 0 {
-        return;
+        return Ok(());
+    }
+    // each table takes one of the pinned lookup buffers
+    if num_tables > Prover::get_num_lookup_tables() {
+        return Err(DecodeError::CapacityOverflow {
+            requested: num_tables,
+            capacity: Prover::get_num_lookup_tables(),
+        });
     }
 
     for _ in 0..num_tables {
         let mut table_name_encoding: Vec<u8> = vec![];
-        deserialize_generic(encoding, &mut table_name_encoding);
-        let table_name: String = bincode::deserialize(&table_name_encoding).unwrap();
+        deserialize_generic(
+            encoding,
+            &mut table_name_encoding,
+            MAX_TABLE_NAME_ENCODING_LEN,
+        )?;
+        let table_name: String = bincode::deserialize(&table_name_encoding)
+            .map_err(|e| DecodeError::InvalidTableName(e.to_string()))?;
+        if assembly.known_table_names.contains(&table_name) {
+            return Err(DecodeError::DuplicateTable(table_name));
+        }
         assembly.known_table_names.push(table_name);
     }
     let table_names = assembly.known_table_names.clone();
 
     let mut table_ids = vec![];
-    deserialize_generic(encoding, &mut table_ids);
-    assert_eq!(table_names.len(), table_ids.len());
+    deserialize_generic(encoding, &mut table_ids, num_tables)?;
+    if table_names.len() != table_ids.len() {
+        return Err(DecodeError::LengthMismatch {
+            field: "table_ids",
+            expected: table_names.len(),
+            actual: table_ids.len(),
+        });
+    }
 
     for (table_name, table_id) in table_names.iter().zip(table_ids) {
         assembly
             .known_table_ids
             .insert(table_name.clone(), table_id);
     }
-    assert_eq!(assembly.known_table_ids.len(), num_tables);
 
     for table_name in table_names.iter() {
         let table = individual_table_canonical_sorted_entries
             .entry(table_name.clone())
             .or_insert_with(|| Vec::with_capacity(0));
-        deserialize_generic(encoding, table);
+        deserialize_generic(encoding, table, MAX_CANONICAL_TABLE_ROWS)?;
     }
-    assert_eq!(individual_table_canonical_sorted_entries.len(), num_tables);
 
     for table_name in table_names.iter() {
         let table = individual_table_entries
@@ -478,9 +1473,10 @@ fn deserialize_tables<R: Read>(encoding: &mut R, assembly: &mut ProvingAssembly)
                     new_vec_with_allocator!(0)
                 }
             });
-        deserialize_generic(encoding, table);
+        deserialize_generic(encoding, table, Prover::get_max_num_lookup_entries())?;
     }
-    assert_eq!(individual_table_entries.len(), num_tables);
+
+    Ok(())
 }
 
 fn serialize_generic<T, W: Write>(data: &[T], buffer: &mut W) {
@@ -492,42 +1488,305 @@ fn serialize_generic<T, W: Write>(data: &[T], buffer: &mut W) {
     buffer.write_all(slice).unwrap();
 }
 
+/// Encoded length of `serialize_generic`
+fn generic_len<T>(data: &[T]) -> u64 {
+    8 + (data.len() * std::mem::size_of::<T>()) as u64
+}
+
+#[cfg(feature = "gpu")]
+fn deserialize_generic<T, A: Allocator, R: Read>(
+    encoding: &mut R,
+    buffer: &mut Vec<T, A>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    let max_len = buffer_len_limit(buffer.capacity(), max_len);
+    let actual_len = deserialize_generic_len::<T, R>(encoding, max_len)?;
+    buffer.clear();
+    buffer
+        .try_reserve(actual_len)
+        .map_err(|_| DecodeError::CapacityOverflow {
+            requested: actual_len,
+            capacity: buffer.capacity(),
+        })?;
+    let ptr = buffer.as_mut_ptr() as *mut u8;
+    let slice =
+        unsafe { std::slice::from_raw_parts_mut(ptr, actual_len * std::mem::size_of::<T>()) };
+    encoding.read_exact(slice)?;
+    unsafe {
+        buffer.set_len(actual_len);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "gpu"))]
+fn deserialize_generic<T, R: Read>(
+    encoding: &mut R,
+    buffer: &mut Vec<T>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    let max_len = buffer_len_limit(buffer.capacity(), max_len);
+    let actual_len = deserialize_generic_len::<T, R>(encoding, max_len)?;
+    buffer.clear();
+    buffer
+        .try_reserve(actual_len)
+        .map_err(|_| DecodeError::CapacityOverflow {
+            requested: actual_len,
+            capacity: buffer.capacity(),
+        })?;
+    let ptr = buffer.as_mut_ptr() as *mut u8;
+    let slice =
+        unsafe { std::slice::from_raw_parts_mut(ptr, actual_len * std::mem::size_of::<T>()) };
+    encoding.read_exact(slice)?;
+    unsafe {
+        buffer.set_len(actual_len);
+    }
+
+    Ok(())
+}
+
+fn serialize_column<T, W: Write>(data: &[T], column_encoding: ColumnEncoding, buffer: &mut W) {
+    match column_encoding {
+        ColumnEncoding::Dense => serialize_generic(data, buffer),
+        ColumnEncoding::RunLength => serialize_run_length(data, buffer),
+    }
+}
+
+/// Encoded length of `serialize_column`
+fn column_len<T>(data: &[T], column_encoding: ColumnEncoding) -> u64 {
+    match column_encoding {
+        ColumnEncoding::Dense => generic_len(data),
+        ColumnEncoding::RunLength => {
+            let unit_len = std::mem::size_of::<T>();
+            let num_runs = count_runs(as_bytes(data), unit_len);
+            // number of elements and runs followed by the runs
+            16 + (num_runs * (8 + unit_len)) as u64
+        }
+    }
+}
+
 #[cfg(feature = "gpu")]
-fn deserialize_generic<T, A: Allocator, R: Read>(encoding: &mut R, buffer: &mut Vec<T, A>) {
+fn deserialize_column<T, A: Allocator, R: Read>(
+    encoding: &mut R,
+    column_encoding: ColumnEncoding,
+    buffer: &mut Vec<T, A>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    match column_encoding {
+        ColumnEncoding::Dense => deserialize_generic(encoding, buffer, max_len),
+        ColumnEncoding::RunLength => deserialize_run_length(encoding, buffer, max_len),
+    }
+}
+
+#[cfg(not(feature = "gpu"))]
+fn deserialize_column<T, R: Read>(
+    encoding: &mut R,
+    column_encoding: ColumnEncoding,
+    buffer: &mut Vec<T>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    match column_encoding {
+        ColumnEncoding::Dense => deserialize_generic(encoding, buffer, max_len),
+        ColumnEncoding::RunLength => deserialize_run_length(encoding, buffer, max_len),
+    }
+}
+
+/// Writes the number of elements and the number of runs followed by every run
+/// as its length and the raw bytes of the repeated element.
+fn serialize_run_length<T, W: Write>(data: &[T], buffer: &mut W) {
     let unit_len = std::mem::size_of::<T>();
+    let bytes = as_bytes(data);
+    let num_runs = count_runs(bytes, unit_len);
+
+    buffer.write_all(&data.len().to_le_bytes()).unwrap();
+    buffer.write_all(&num_runs.to_le_bytes()).unwrap();
+    for_each_run(bytes, unit_len, |run_len, element| {
+        buffer.write_all(&run_len.to_le_bytes()).unwrap();
+        buffer.write_all(element).unwrap();
+    });
+}
 
-    let mut buf_num_bytes = [0u8; 8];
-    encoding.read_exact(&mut buf_num_bytes).unwrap();
-    let len = usize::from_le_bytes(buf_num_bytes);
-    let actual_len = len / unit_len;
-    assert!(len % unit_len == 0);
-    if buffer.capacity() < actual_len {
-        buffer.reserve(actual_len - buffer.capacity());
+fn as_bytes<T>(data: &[T]) -> &[u8] {
+    let ptr = data.as_ptr() as *const u8;
+    unsafe { std::slice::from_raw_parts(ptr, std::mem::size_of_val(data)) }
+}
+
+fn count_runs(bytes: &[u8], unit_len: usize) -> usize {
+    let mut num_runs = 0;
+    for_each_run(bytes, unit_len, |_, _| num_runs += 1);
+    num_runs
+}
+
+fn for_each_run<F: FnMut(usize, &[u8])>(bytes: &[u8], unit_len: usize, mut f: F) {
+    let mut elements = bytes.chunks_exact(unit_len);
+    let mut current = match elements.next() {
+        Some(element) => element,
+        None => return,
+    };
+    let mut run_len = 1;
+    for element in elements {
+        if element == current {
+            run_len += 1;
+        } else {
+            f(run_len, current);
+            current = element;
+            run_len = 1;
+        }
     }
+    f(run_len, current);
+}
+
+#[cfg(feature = "gpu")]
+fn deserialize_run_length<T, A: Allocator, R: Read>(
+    encoding: &mut R,
+    buffer: &mut Vec<T, A>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    let max_len = buffer_len_limit(buffer.capacity(), max_len);
+    let (num_elements, num_runs) = deserialize_run_length_prefix(encoding, max_len)?;
+    buffer.clear();
+    buffer
+        .try_reserve(num_elements)
+        .map_err(|_| DecodeError::CapacityOverflow {
+            requested: num_elements,
+            capacity: buffer.capacity(),
+        })?;
     let ptr = buffer.as_mut_ptr() as *mut u8;
-    let slice = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
+    let slice =
+        unsafe { std::slice::from_raw_parts_mut(ptr, num_elements * std::mem::size_of::<T>()) };
+    expand_runs(encoding, slice, std::mem::size_of::<T>(), num_runs)?;
     unsafe {
-        buffer.set_len(actual_len);
+        buffer.set_len(num_elements);
     }
-    encoding.read_exact(slice).unwrap();
+
+    Ok(())
 }
 
 #[cfg(not(feature = "gpu"))]
-fn deserialize_generic<T, R: Read>(encoding: &mut R, buffer: &mut Vec<T>) {
+fn deserialize_run_length<T, R: Read>(
+    encoding: &mut R,
+    buffer: &mut Vec<T>,
+    max_len: usize,
+) -> Result<(), DecodeError> {
+    let max_len = buffer_len_limit(buffer.capacity(), max_len);
+    let (num_elements, num_runs) = deserialize_run_length_prefix(encoding, max_len)?;
+    buffer.clear();
+    buffer
+        .try_reserve(num_elements)
+        .map_err(|_| DecodeError::CapacityOverflow {
+            requested: num_elements,
+            capacity: buffer.capacity(),
+        })?;
+    let ptr = buffer.as_mut_ptr() as *mut u8;
+    let slice =
+        unsafe { std::slice::from_raw_parts_mut(ptr, num_elements * std::mem::size_of::<T>()) };
+    expand_runs(encoding, slice, std::mem::size_of::<T>(), num_runs)?;
+    unsafe {
+        buffer.set_len(num_elements);
+    }
+
+    Ok(())
+}
+
+fn deserialize_run_length_prefix<R: Read>(
+    encoding: &mut R,
+    max_len: usize,
+) -> Result<(usize, usize), DecodeError> {
+    let mut bytes = [0u8; 8];
+    encoding.read_exact(&mut bytes)?;
+    let num_elements = usize::from_le_bytes(bytes);
+    if num_elements > max_len {
+        return Err(DecodeError::CapacityOverflow {
+            requested: num_elements,
+            capacity: max_len,
+        });
+    }
+    encoding.read_exact(&mut bytes)?;
+    let num_runs = usize::from_le_bytes(bytes);
+    if num_runs > num_elements {
+        return Err(DecodeError::LengthMismatch {
+            field: "num_runs",
+            expected: num_elements,
+            actual: num_runs,
+        });
+    }
+
+    Ok((num_elements, num_runs))
+}
+
+/// Fills `dst` with `num_runs` runs, every run must fit into the remaining space
+/// and all of them together must cover `dst` exactly.
+fn expand_runs<R: Read>(
+    encoding: &mut R,
+    dst: &mut [u8],
+    unit_len: usize,
+    num_runs: usize,
+) -> Result<(), DecodeError> {
+    let num_elements = dst.len() / unit_len;
+    let mut element = vec![0u8; unit_len];
+    let mut len_bytes = [0u8; 8];
+    let mut offset = 0;
+    for _ in 0..num_runs {
+        encoding.read_exact(&mut len_bytes)?;
+        let run_len = usize::from_le_bytes(len_bytes);
+        if run_len > num_elements - offset {
+            return Err(DecodeError::LengthMismatch {
+                field: "run_length",
+                expected: num_elements - offset,
+                actual: run_len,
+            });
+        }
+        encoding.read_exact(&mut element)?;
+        for chunk in
+            dst[offset * unit_len..(offset + run_len) * unit_len].chunks_exact_mut(unit_len)
+        {
+            chunk.copy_from_slice(&element);
+        }
+        offset += run_len;
+    }
+    if offset != num_elements {
+        return Err(DecodeError::LengthMismatch {
+            field: "run_length",
+            expected: num_elements,
+            actual: offset,
+        });
+    }
+
+    Ok(())
+}
+
+/// Number of elements a decoded buffer may hold. Preallocated buffers are pinned
+/// memory that is never reallocated, so they are bounded by their capacity as well,
+/// only empty buffers are allocated while decoding.
+fn buffer_len_limit(capacity: usize, max_len: usize) -> usize {
+    match capacity {
+        0 => max_len,
+        capacity => capacity.min(max_len),
+    }
+}
+
+/// Reads the byte length prefix written by `serialize_generic` and converts it into
+/// the number of elements of type `T`. Lengths above `max_len` are rejected before any
+/// memory is touched.
+fn deserialize_generic_len<T, R: Read>(
+    encoding: &mut R,
+    max_len: usize,
+) -> Result<usize, DecodeError> {
     let unit_len = std::mem::size_of::<T>();
 
     let mut buf_num_bytes = [0u8; 8];
-    encoding.read_exact(&mut buf_num_bytes).unwrap();
+    encoding.read_exact(&mut buf_num_bytes)?;
     let len = usize::from_le_bytes(buf_num_bytes);
-    let actual_len = len / unit_len;
-    assert!(len % unit_len == 0);
-    if buffer.capacity() < actual_len {
-        buffer.reserve(actual_len - buffer.capacity());
+    if len % unit_len != 0 {
+        return Err(DecodeError::MisalignedLength { len, unit_len });
     }
-    let ptr = buffer.as_mut_ptr() as *mut u8;
-    let slice = unsafe { std::slice::from_raw_parts_mut(ptr, len) };
-    unsafe {
-        buffer.set_len(actual_len);
+    let actual_len = len / unit_len;
+    if actual_len > max_len {
+        return Err(DecodeError::CapacityOverflow {
+            requested: actual_len,
+            capacity: max_len,
+        });
     }
-    encoding.read_exact(slice).unwrap();
+
+    Ok(actual_len)
 }
@@ -0,0 +1,195 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, SyncSender, TrySendError},
+    },
+    thread::JoinHandle,
+};
+
+use super::*;
+use crate::events::JobEvent;
+
+/// Hands every report to each of the reporters in order.
+#[derive(Default)]
+pub struct MultiJobReporter {
+    reporters: Vec<Box<dyn JobReporter>>,
+}
+
+impl MultiJobReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with<R: JobReporter + 'static>(mut self, reporter: R) -> Self {
+        self.reporters.push(Box::new(reporter));
+        self
+    }
+}
+
+impl JobReporter for MultiJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        for reporter in self.reporters.iter_mut() {
+            reporter.send_report(report.clone());
+        }
+    }
+
+    fn send_event(&mut self, event: JobEvent) {
+        for reporter in self.reporters.iter_mut() {
+            reporter.send_event(event.clone());
+        }
+    }
+}
+
+/// Passes on the reports matching the predicate only.
+pub struct FilterJobReporter<R: JobReporter, F: FnMut(&JobResult) -> bool + Send> {
+    inner: R,
+    predicate: F,
+}
+
+impl<R: JobReporter, F: FnMut(&JobResult) -> bool + Send> FilterJobReporter<R, F> {
+    pub fn new(inner: R, predicate: F) -> Self {
+        Self { inner, predicate }
+    }
+}
+
+impl<R: JobReporter> FilterJobReporter<R, fn(&JobResult) -> bool> {
+    /// Failed, corrupted and exhausted jobs
+    pub fn failures(inner: R) -> Self {
+        Self::new(inner, |report| {
+            matches!(
+                report,
+                JobResult::Failure(_, _)
+                    | JobResult::FailureWithDebugging(_, _, _, _)
+                    | JobResult::AssemblyCorrupted(_, _)
+                    | JobResult::RetriesExhausted(_, _)
+            )
+        })
+    }
+
+    pub fn proofs(inner: R) -> Self {
+        Self::new(inner, |report| {
+            matches!(report, JobResult::ProofGenerated(_, _, _, _))
+        })
+    }
+}
+
+impl<R: JobReporter, F: FnMut(&JobResult) -> bool + Send> JobReporter for FilterJobReporter<R, F> {
+    fn send_report(&mut self, report: JobResult) {
+        if (self.predicate)(&report) {
+            self.inner.send_report(report);
+        }
+    }
+
+    fn send_event(&mut self, event: JobEvent) {
+        if (self.predicate)(&event.result) {
+            self.inner.send_event(event);
+        }
+    }
+}
+
+/// Rewrites the reports before passing them on, `None` drops the report.
+pub struct MapJobReporter<R: JobReporter, F: FnMut(JobResult) -> Option<JobResult> + Send> {
+    inner: R,
+    map: F,
+}
+
+impl<R: JobReporter, F: FnMut(JobResult) -> Option<JobResult> + Send> MapJobReporter<R, F> {
+    pub fn new(inner: R, map: F) -> Self {
+        Self { inner, map }
+    }
+}
+
+impl<R: JobReporter, F: FnMut(JobResult) -> Option<JobResult> + Send> JobReporter
+    for MapJobReporter<R, F>
+{
+    fn send_report(&mut self, report: JobResult) {
+        if let Some(report) = (self.map)(report) {
+            self.inner.send_report(report);
+        }
+    }
+
+    fn send_event(&mut self, mut event: JobEvent) {
+        if let Some(report) = (self.map)(event.result) {
+            event.result = report;
+            self.inner.send_event(event);
+        }
+    }
+}
+
+enum Buffered {
+    Report(JobResult),
+    Event(JobEvent),
+}
+
+/// Runs the inner reporter on its own thread so that a slow reporter can't
+/// stall the service. Reports that don't fit into the buffer are dropped and
+/// counted, dropping the reporter flushes the buffer.
+pub struct BufferedJobReporter {
+    sender: Option<SyncSender<Buffered>>,
+    worker: Option<JoinHandle<()>>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl BufferedJobReporter {
+    pub fn new<R: JobReporter + 'static>(mut inner: R, capacity: usize) -> Self {
+        let (sender, receiver) = sync_channel(capacity);
+        let worker = std::thread::Builder::new()
+            .name("buffered-job-reporter".to_string())
+            .spawn(move || {
+                for message in receiver {
+                    match message {
+                        Buffered::Report(report) => inner.send_report(report),
+                        Buffered::Event(event) => inner.send_event(event),
+                    }
+                }
+            })
+            .expect("failed spawning reporter thread");
+
+        Self {
+            sender: Some(sender),
+            worker: Some(worker),
+            dropped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of reports dropped because the buffer was full
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    fn push(&self, message: Buffered) {
+        let sender = self.sender.as_ref().expect("reporter is dropped");
+        match sender.try_send(message) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                let dropped = self.dropped.fetch_add(1, Ordering::SeqCst) + 1;
+                if dropped.is_power_of_two() {
+                    println!("reporter buffer is full, dropped {} reports", dropped);
+                }
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.dropped.fetch_add(1, Ordering::SeqCst);
+                println!("buffered reporter thread is gone, dropping report");
+            }
+        }
+    }
+}
+
+impl JobReporter for BufferedJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        self.push(Buffered::Report(report))
+    }
+
+    fn send_event(&mut self, event: JobEvent) {
+        self.push(Buffered::Event(event))
+    }
+}
+
+impl Drop for BufferedJobReporter {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::{collections::HashMap, hash::Hasher};
+
+use twox_hash::XxHash64;
+
+/// Decides which prover server receives an encoded assembly.
+pub trait ArtifactRouter: Send {
+    /// Returns the index of the artifact sender for an assembly of the given circuit type.
+    /// `loads` holds the number of assemblies each sender is currently busy with,
+    /// `None` means that no server accepts the circuit type.
+    fn route(&mut self, circuit_id: u8, loads: &[usize]) -> Option<usize>;
+}
+
+/// Fixed circuit type to server assignment, circuits without a route go to the fallback.
+pub struct StaticRouter {
+    routes: HashMap<u8, usize>,
+    fallback: Option<usize>,
+}
+
+impl StaticRouter {
+    pub fn new(routes: HashMap<u8, usize>, fallback: Option<usize>) -> Self {
+        Self { routes, fallback }
+    }
+}
+
+impl ArtifactRouter for StaticRouter {
+    fn route(&mut self, circuit_id: u8, _loads: &[usize]) -> Option<usize> {
+        self.routes.get(&circuit_id).copied().or(self.fallback)
+    }
+}
+
+#[derive(Default)]
+pub struct RoundRobinRouter {
+    next: usize,
+}
+
+impl ArtifactRouter for RoundRobinRouter {
+    fn route(&mut self, _circuit_id: u8, loads: &[usize]) -> Option<usize> {
+        if loads.is_empty() {
+            return None;
+        }
+        let sender_idx = self.next % loads.len();
+        self.next = sender_idx + 1;
+
+        Some(sender_idx)
+    }
+}
+
+/// Picks the server with the fewest assemblies in flight, ties go to the lowest index.
+#[derive(Default)]
+pub struct LeastLoadedRouter;
+
+impl ArtifactRouter for LeastLoadedRouter {
+    fn route(&mut self, _circuit_id: u8, loads: &[usize]) -> Option<usize> {
+        loads
+            .iter()
+            .enumerate()
+            .min_by_key(|(idx, load)| (**load, *idx))
+            .map(|(idx, _)| idx)
+    }
+}
+
+/// Spreads circuit types over a hash ring so that every server keeps receiving the same
+/// circuit types (and therefore keeps their setups resident) and adding a server only
+/// moves a fraction of them.
+pub struct ConsistentHashRouter {
+    ring: Vec<(u64, usize)>,
+}
+
+impl ConsistentHashRouter {
+    pub fn new(num_senders: usize, replicas_per_sender: usize) -> Self {
+        let mut ring = vec![];
+        for sender_idx in 0..num_senders {
+            for replica in 0..replicas_per_sender {
+                let mut hasher = XxHash64::default();
+                hasher.write_usize(sender_idx);
+                hasher.write_usize(replica);
+                ring.push((hasher.finish(), sender_idx));
+            }
+        }
+        ring.sort_unstable();
+
+        Self { ring }
+    }
+}
+
+impl ArtifactRouter for ConsistentHashRouter {
+    fn route(&mut self, circuit_id: u8, _loads: &[usize]) -> Option<usize> {
+        let mut hasher = XxHash64::default();
+        hasher.write_u8(circuit_id);
+        let hash = hasher.finish();
+        let position = self.ring.partition_point(|(point, _)| *point < hash);
+
+        self.ring
+            .get(position)
+            .or(self.ring.first())
+            .map(|(_, sender_idx)| *sender_idx)
+    }
+}
@@ -1,9 +1,11 @@
 use prover::ProvingAssembly;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::mpsc::channel;
-use std::sync::RwLock;
+use std::thread::JoinHandle;
 use std::time::Duration;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::mpsc::{Receiver, Sender},
 };
 use zkevm_test_harness::bellman::bn256::Fr;
@@ -13,10 +15,15 @@ use zkevm_test_harness::{
     sync_vm::utils::bn254_rescue_params,
 };
 
+use crate::events::{JobContexts, JobEvent, ReportSender};
 use crate::remote_synth::{
-    calculate_serialization_capacity_for_proving_assembly, deserialize_job, serialize_job,
+    calculate_serialization_capacity_for_proving_assembly, deserialize_job_header,
+    deserialize_sections, serialize_job, ChecksumReader, EncodingHeader,
 };
+use crate::scheduling::PriorityQueue;
 use crate::setup::ZkSyncSetup;
+use crate::setup_cache::{SetupCache, TakenSlot};
+use crate::worker_pool::{WorkerPool, WorkerPoolKind, WorkerPoolStats};
 
 pub struct GenericReceiver<T>(Receiver<T>);
 unsafe impl<T> Send for GenericReceiver<T> {}
@@ -27,7 +34,7 @@ use super::*;
 pub struct ThreadGuard {
     job_id: usize,
     thread: u8,
-    report_sender: Sender<JobResult>,
+    report_sender: ReportSender,
     thread_status_sender: Sender<u8>,
 }
 
@@ -36,7 +43,7 @@ impl ThreadGuard {
     pub fn new(
         thread: u8,
         job_id: usize,
-        report_sender: Sender<JobResult>,
+        report_sender: ReportSender,
         thread_status_sender: Sender<u8>,
     ) -> Self {
         Self {
@@ -91,10 +98,38 @@ pub(crate) struct ProverContext {
     main_prover_input_sender: Sender<ProverMessage>,
     reusable_assembly_sender: Sender<ProvingAssembly>,
     reusable_assembly_receiver: Receiver<ProvingAssembly>,
+    // number of assemblies waiting in the reusable pool
+    free_assemblies: AtomicUsize,
+    num_parallel_synthesis: usize,
+    // set once the scheduler should stop taking new jobs
+    stopping: AtomicBool,
+    // set once the service threads should exit
+    terminated: AtomicBool,
+    // set once the preloaded setups are resident
+    ready: AtomicBool,
+    service_threads: Mutex<Vec<JoinHandle<()>>>,
+    job_threads: Mutex<Vec<JoinHandle<()>>>,
+    synthesis_pool: WorkerPool,
+    decoding_pool: WorkerPool,
+    proving_pool: WorkerPool,
+    // jobs taken by the scheduler with their priorities
+    in_flight_jobs: Mutex<HashMap<JobId, JobPriority>>,
+    cancelled_jobs: Mutex<HashSet<JobId>>,
+    // stage each job is currently in with its start time and the prover it holds
+    job_stages: Mutex<HashMap<JobId, (JobStage, std::time::Instant, Option<ProverId>)>>,
+    // jobs abandoned by the watchdog
+    timed_out_jobs: Mutex<HashSet<JobId>>,
+    // abandoned jobs whose assemblies the watchdog replaced with fresh ones
+    replaced_assemblies: Mutex<HashSet<JobId>>,
+    // failed proving attempts per job
+    failed_attempts: Mutex<HashMap<JobId, usize>>,
+    // circuit types whose setups are loaded right now
+    resident_setups: Mutex<HashSet<u8>>,
     pub(crate) thread_status_sender: Sender<u8>,
     thread_status_receiver: Receiver<u8>,
-    pub(crate) report_sender: Sender<JobResult>,
-    report_receiver: Receiver<JobResult>,
+    pub(crate) report_sender: ReportSender,
+    report_receiver: Receiver<JobEvent>,
+    job_contexts: Arc<JobContexts>,
     num_provers: usize,
     specialized_circuit_ids: Option<Vec<u8>>,
 }
@@ -103,11 +138,14 @@ unsafe impl Send for ProverContext {}
 unsafe impl Sync for ProverContext {}
 
 impl ProverContext {
-    fn init(circuit_ids: Option<Vec<u8>>, num_parallel_synthesis: u8) -> Self {
+    fn init<P: Params>(circuit_ids: Option<Vec<u8>>, params: &P) -> Self {
+        let num_parallel_synthesis = params.number_of_parallel_synthesis();
         let (prover_input_sender, prover_input_receiver) = channel();
         let (prover_instance_sender, prover_instance_receiver) = channel();
         let (reusable_assembly_sender, reusable_assembly_receiver) = channel();
         let (report_sender, report_receiver) = channel();
+        let job_contexts = Arc::new(JobContexts::default());
+        let report_sender = ReportSender::new(report_sender, job_contexts.clone());
         let (thread_status_sender, thread_status_receiver) = channel();
 
         let mut setup_input_receiver = None;
@@ -127,6 +165,9 @@ impl ProverContext {
                 .unwrap()
         }
 
+        for (prover_idx, device_ids) in prover_device_ids().into_iter().enumerate() {
+            job_contexts.set_prover_device_ids(prover_idx, device_ids);
+        }
         let provers = create_prover_instances();
         let num_provers = provers.len();
         for (prover_idx, prover) in provers {
@@ -142,10 +183,37 @@ impl ProverContext {
             prover_instance_receiver,
             reusable_assembly_sender,
             reusable_assembly_receiver,
+            free_assemblies: AtomicUsize::new(num_parallel_synthesis as usize),
+            num_parallel_synthesis: num_parallel_synthesis as usize,
+            stopping: AtomicBool::new(false),
+            terminated: AtomicBool::new(false),
+            ready: AtomicBool::new(false),
+            service_threads: Mutex::new(vec![]),
+            job_threads: Mutex::new(vec![]),
+            synthesis_pool: WorkerPool::new(
+                "synthesis",
+                params.worker_pool_config(WorkerPoolKind::Synthesis),
+            ),
+            decoding_pool: WorkerPool::new(
+                "decoding",
+                params.worker_pool_config(WorkerPoolKind::Decoding),
+            ),
+            proving_pool: WorkerPool::new(
+                "proving",
+                params.worker_pool_config(WorkerPoolKind::Proving),
+            ),
+            in_flight_jobs: Mutex::new(HashMap::new()),
+            cancelled_jobs: Mutex::new(HashSet::new()),
+            job_stages: Mutex::new(HashMap::new()),
+            timed_out_jobs: Mutex::new(HashSet::new()),
+            replaced_assemblies: Mutex::new(HashSet::new()),
+            failed_attempts: Mutex::new(HashMap::new()),
+            resident_setups: Mutex::new(HashSet::new()),
             thread_status_sender,
             thread_status_receiver,
             report_sender,
             report_receiver,
+            job_contexts,
             main_prover_input_sender,
             num_provers,
             specialized_circuit_ids: circuit_ids,
@@ -153,6 +221,295 @@ impl ProverContext {
     }
 }
 
+impl ProverContext {
+    fn try_take_reusable_assembly(&self, timeout: Duration) -> Option<ProvingAssembly> {
+        let assembly = self.reusable_assembly_receiver.recv_timeout(timeout).ok()?;
+        self.free_assemblies.fetch_sub(1, Ordering::SeqCst);
+        Some(assembly)
+    }
+
+    fn return_reusable_assembly(&self, assembly: ProvingAssembly) {
+        self.free_assemblies.fetch_add(1, Ordering::SeqCst);
+        self.reusable_assembly_sender.send(assembly).unwrap();
+    }
+
+    /// Puts a fresh assembly into the pool in place of the one held by the
+    /// abandoned job, at most one replacement per reusable assembly is outstanding
+    fn replace_assembly(&self, job_id: JobId) -> bool {
+        let mut replaced_assemblies = self.replaced_assemblies.lock().unwrap();
+        if replaced_assemblies.len() >= self.num_parallel_synthesis {
+            return false;
+        }
+        replaced_assemblies.insert(job_id);
+        drop(replaced_assemblies);
+        self.return_reusable_assembly(Prover::new_proving_assembly());
+        true
+    }
+
+    /// Every job holds a reusable assembly until it is done, a job abandoned by
+    /// the watchdog is still running until its replaced assembly comes back
+    fn is_drained(&self) -> bool {
+        self.free_assemblies.load(Ordering::SeqCst) == self.num_parallel_synthesis
+            && self.replaced_assemblies.lock().unwrap().is_empty()
+    }
+
+    fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    fn is_terminated(&self) -> bool {
+        self.terminated.load(Ordering::SeqCst)
+    }
+
+    /// Reports the end of the warm-up, only the first time so that a respawned
+    /// setup handler doesn't report it again
+    fn mark_ready(&self, warm_up: Duration) {
+        if self.ready.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.report_sender
+            .send(JobResult::ServiceReady(warm_up))
+            .unwrap();
+    }
+
+    fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::SeqCst)
+    }
+
+    /// Blocks the scheduler until the warm-up is done or the service stops
+    fn wait_until_ready(&self, polling_duration: Duration) {
+        while !self.is_ready() && !self.is_stopping() {
+            sleep_for_duration(polling_duration);
+        }
+    }
+
+    fn start_job(&self, job_id: JobId, circuit_id: u8, priority: JobPriority) {
+        self.job_contexts.start(job_id, circuit_id);
+        self.in_flight_jobs.lock().unwrap().insert(job_id, priority);
+    }
+
+    fn is_in_flight(&self, job_id: JobId) -> bool {
+        self.in_flight_jobs.lock().unwrap().contains_key(&job_id)
+    }
+
+    fn job_priority(&self, job_id: JobId) -> JobPriority {
+        self.in_flight_jobs
+            .lock()
+            .unwrap()
+            .get(&job_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Forgets the job
+    fn finish_job(&self, job_id: JobId) {
+        self.in_flight_jobs.lock().unwrap().remove(&job_id);
+        self.cancelled_jobs.lock().unwrap().remove(&job_id);
+        self.job_stages.lock().unwrap().remove(&job_id);
+        self.failed_attempts.lock().unwrap().remove(&job_id);
+        self.job_contexts.finish(job_id);
+        self.timed_out_jobs.lock().unwrap().remove(&job_id);
+    }
+
+    /// Counts a failed attempt of the job and returns its number
+    fn record_failed_attempt(&self, job_id: JobId) -> usize {
+        let mut failed_attempts = self.failed_attempts.lock().unwrap();
+        let attempt = failed_attempts.entry(job_id).or_insert(0);
+        *attempt += 1;
+        *attempt
+    }
+
+    /// Reports a failure the service won't retry, preceded by the attempts made
+    fn report_final_failure(&self, job_id: JobId, failure_class: FailureClass, report: JobResult) {
+        let attempt = self.record_failed_attempt(job_id);
+        println!(
+            "job {} failed after {} attempts: {:?}",
+            job_id, attempt, failure_class
+        );
+        self.report_sender
+            .send(JobResult::RetriesExhausted(job_id, attempt))
+            .unwrap();
+        self.report_sender.send(report).unwrap();
+    }
+
+    /// Forgets the job and hands its assembly back to the pool, the surplus
+    /// assembly of a job the watchdog has replaced is freed instead
+    fn release_job(&self, job_id: JobId, assembly: ProvingAssembly) {
+        self.finish_job(job_id);
+        if self.replaced_assemblies.lock().unwrap().remove(&job_id) {
+            return;
+        }
+        self.return_reusable_assembly(assembly);
+    }
+
+    fn is_cancelled(&self, job_id: JobId) -> bool {
+        self.cancelled_jobs.lock().unwrap().contains(&job_id)
+    }
+
+    fn is_timed_out(&self, job_id: JobId) -> bool {
+        self.timed_out_jobs.lock().unwrap().contains(&job_id)
+    }
+
+    /// Whether the job should be dropped at the next stage boundary
+    fn is_aborted(&self, job_id: JobId) -> bool {
+        self.is_cancelled(job_id) || self.is_timed_out(job_id)
+    }
+
+    fn enter_stage(&self, job_id: JobId, stage: JobStage, prover_idx: Option<ProverId>) {
+        self.job_contexts.enter_stage(job_id, stage, prover_idx);
+        self.job_stages
+            .lock()
+            .unwrap()
+            .insert(job_id, (stage, std::time::Instant::now(), prover_idx));
+    }
+
+    /// Waiting in a queue between stages doesn't count against any deadline
+    fn leave_stage(&self, job_id: JobId) {
+        self.job_stages.lock().unwrap().remove(&job_id);
+    }
+
+    fn set_resident_setups<I: IntoIterator<Item = u8>>(&self, circuit_ids: I) {
+        *self.resident_setups.lock().unwrap() = circuit_ids.into_iter().collect();
+    }
+
+    fn is_setup_resident(&self, circuit_id: u8) -> bool {
+        self.resident_setups.lock().unwrap().contains(&circuit_id)
+    }
+
+    /// Abandons jobs that stay in a stage for longer than its deadline
+    fn take_expired_stages<F: Fn(JobStage) -> Option<Duration>>(
+        &self,
+        stage_timeout: F,
+    ) -> Vec<(JobId, JobStage, Duration, Option<ProverId>)> {
+        let mut job_stages = self.job_stages.lock().unwrap();
+        let mut timed_out_jobs = self.timed_out_jobs.lock().unwrap();
+        let mut expired = vec![];
+        job_stages.retain(|job_id, (stage, started, prover_idx)| {
+            let elapsed = started.elapsed();
+            match stage_timeout(*stage) {
+                Some(timeout) if elapsed > timeout => {
+                    timed_out_jobs.insert(*job_id);
+                    expired.push((*job_id, *stage, elapsed, *prover_idx));
+                    false
+                }
+                _ => true,
+            }
+        });
+
+        expired
+    }
+
+    /// Asks the source of the jobs about every in-flight job and flags the cancelled ones
+    fn poll_cancellations<F: FnMut(JobId) -> bool>(&self, mut is_cancelled: F) {
+        let in_flight_jobs: Vec<JobId> = self
+            .in_flight_jobs
+            .lock()
+            .unwrap()
+            .keys()
+            .copied()
+            .collect();
+        let mut cancelled_jobs = self.cancelled_jobs.lock().unwrap();
+        for job_id in in_flight_jobs {
+            if !cancelled_jobs.contains(&job_id) && is_cancelled(job_id) {
+                println!("job {} is cancelled", job_id);
+                cancelled_jobs.insert(job_id);
+            }
+        }
+    }
+
+    /// Drops a cancelled or timed out job between stages, timed out jobs are reported already
+    fn abort_job(&self, job_id: JobId, assembly: ProvingAssembly) {
+        if !self.is_timed_out(job_id) {
+            self.report_sender
+                .send(JobResult::Cancelled(job_id))
+                .unwrap();
+        }
+        self.release_job(job_id, recycle_assembly(assembly));
+    }
+
+    /// Spawns a long living thread that runs until the service is terminated
+    fn spawn_service<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let handle = std::thread::spawn(f);
+        self.service_threads.lock().unwrap().push(handle);
+    }
+
+    fn worker_pool(&self, kind: WorkerPoolKind) -> &WorkerPool {
+        match kind {
+            WorkerPoolKind::Synthesis => &self.synthesis_pool,
+            WorkerPoolKind::Decoding => &self.decoding_pool,
+            WorkerPoolKind::Proving => &self.proving_pool,
+        }
+    }
+
+    /// Spawns a thread that waits on behalf of a single job
+    fn spawn_job<F: FnOnce() + Send + 'static>(&self, f: F) {
+        let handle = std::thread::spawn(f);
+        let mut job_threads = self.job_threads.lock().unwrap();
+        job_threads.retain(|thread| !thread.is_finished());
+        job_threads.push(handle);
+    }
+}
+
+/// Returned by the prover service loops to stop them.
+pub struct ShutdownHandle {
+    ctx: Arc<ProverContext>,
+    scheduler: JoinHandle<()>,
+    polling_duration: Duration,
+}
+
+impl ShutdownHandle {
+    /// Blocks for as long as the service is running.
+    pub fn wait(self) {
+        let _ = self.scheduler.join();
+    }
+
+    pub fn worker_pool_stats(&self, kind: WorkerPoolKind) -> WorkerPoolStats {
+        self.ctx.worker_pool(kind).stats()
+    }
+
+    /// Stops taking new jobs and waits up to `timeout` for in-flight jobs to finish.
+    /// Then flushes pending reports and joins all threads. Returns `false` if in-flight
+    /// jobs didn't finish in time, their threads are left detached.
+    pub fn shutdown(self, timeout: Duration) -> bool {
+        self.ctx.stopping.store(true, Ordering::SeqCst);
+        let _ = self.scheduler.join();
+
+        let draining_started = std::time::Instant::now();
+        while !self.ctx.is_drained() && draining_started.elapsed() < timeout {
+            sleep_for_duration(self.polling_duration);
+        }
+        let is_drained = self.ctx.is_drained();
+        if !is_drained {
+            println!(
+                "shutdown timed out with {} jobs in flight",
+                self.ctx.num_parallel_synthesis - self.ctx.free_assemblies.load(Ordering::SeqCst)
+            );
+        }
+
+        // liveness tracker flushes pending reports on its way out
+        self.ctx.terminated.store(true, Ordering::SeqCst);
+        let service_threads = std::mem::take(&mut *self.ctx.service_threads.lock().unwrap());
+        for thread in service_threads {
+            let _ = thread.join();
+        }
+        if is_drained {
+            let job_threads = std::mem::take(&mut *self.ctx.job_threads.lock().unwrap());
+            for thread in job_threads {
+                let _ = thread.join();
+            }
+        }
+        for kind in [
+            WorkerPoolKind::Synthesis,
+            WorkerPoolKind::Decoding,
+            WorkerPoolKind::Proving,
+        ] {
+            self.ctx.worker_pool(kind).shutdown(is_drained);
+        }
+
+        is_drained
+    }
+}
+
 fn check_job_is_allowed(ctx: &ProverContext, job_id: usize, circuit_id: u8) -> bool {
     if let Some(ref circuit_ids) = ctx.specialized_circuit_ids {
         if !circuit_ids.contains(&circuit_id) {
@@ -170,7 +527,7 @@ fn check_job_is_allowed(ctx: &ProverContext, job_id: usize, circuit_id: u8) -> b
 
 pub fn run_prover_with_local_synthesizer<
     AM: ArtifactProvider + 'static,
-    JM: JobManager,
+    JM: JobManager + 'static,
     JR: JobReporter + 'static,
     P: Params + 'static,
 >(
@@ -179,8 +536,8 @@ pub fn run_prover_with_local_synthesizer<
     job_reporter: JR,
     circuit_ids: Option<Vec<u8>>,
     params: P,
-) {
-    let ctx = ProverContext::init(circuit_ids.clone(), params.number_of_parallel_synthesis());
+) -> ShutdownHandle {
+    let ctx = ProverContext::init(circuit_ids.clone(), &params);
     let ctx = Arc::new(ctx);
 
     let params = Arc::new(params);
@@ -201,19 +558,83 @@ pub fn run_prover_with_local_synthesizer<
         circuit_ids,
         params.clone(),
     );
-    let mut scheduler_is_idle = std::time::Instant::now();
-    loop {
-        let reusable_assembly = ctx.reusable_assembly_receiver.recv().unwrap();
-        let (job_id, circuit) = job_manager.get_next_job();
-        if check_job_is_allowed(ctx.as_ref(), job_id, circuit.numeric_circuit_type()) == false {
-            continue;
+    let polling_duration = params.polling_duration();
+    let lookahead = params.scheduler_lookahead().max(1);
+    let setup_affinity_window = params.setup_affinity_window();
+    let mut pending_jobs = PriorityQueue::new(params.priority_aging_interval());
+    let scheduler_ctx = ctx.clone();
+    let scheduler = std::thread::spawn(move || {
+        let ctx = scheduler_ctx;
+        ctx.wait_until_ready(polling_duration);
+        let mut scheduler_is_idle = std::time::Instant::now();
+        let mut last_circuit_id = None;
+        while !ctx.is_stopping() {
+            ctx.poll_cancellations(|job_id| job_manager.is_cancelled(job_id));
+            // don't start more jobs than there are assemblies for
+            let free_assemblies = ctx.free_assemblies.load(Ordering::SeqCst);
+            while pending_jobs.len() < lookahead.min(free_assemblies).max(1) {
+                let job = if pending_jobs.is_empty() {
+                    // wait for a job, briefly so that the scheduler notices the shutdown
+                    job_manager.get_next_job_timeout(JOB_RECHECK_INTERVAL)
+                } else {
+                    job_manager.try_get_next_job()
+                };
+                let (job_id, circuit) = match job {
+                    Some(job) => job,
+                    None => break,
+                };
+                let circuit_id = circuit.numeric_circuit_type();
+                if check_job_is_allowed(ctx.as_ref(), job_id, circuit_id) == false {
+                    continue;
+                }
+                let priority = job_manager.job_priority(job_id, circuit_id);
+                ctx.start_job(job_id, circuit_id, priority);
+                pending_jobs.push((job_id, circuit), priority);
+            }
+            if pending_jobs.is_empty() {
+                continue;
+            }
+            let reusable_assembly = match ctx.try_take_reusable_assembly(polling_duration) {
+                Some(reusable_assembly) => reusable_assembly,
+                None => continue,
+            };
+            // batch circuits of the same type so that their setup is loaded once
+            let (job_id, circuit) = pending_jobs
+                .pop_preferring(
+                    |(_, circuit): &(JobId, ZkSyncCircuit)| {
+                        let circuit_id = circuit.numeric_circuit_type();
+                        last_circuit_id == Some(circuit_id) || ctx.is_setup_resident(circuit_id)
+                    },
+                    setup_affinity_window,
+                )
+                .unwrap();
+            last_circuit_id = Some(circuit.numeric_circuit_type());
+            let scheduler_received_input = scheduler_is_idle.elapsed();
+            ctx.report_sender
+                .send_with_job(
+                    job_id,
+                    JobResult::SchedulerWaitedIdle(scheduler_received_input),
+                )
+                .unwrap();
+            spawn_new_synthesize(ctx.clone(), reusable_assembly, job_id, circuit);
+            scheduler_is_idle = std::time::Instant::now();
         }
-        let scheduler_received_input = scheduler_is_idle.elapsed();
-        ctx.report_sender
-            .send(JobResult::SchedulerWaitedIdle(scheduler_received_input))
-            .unwrap();
-        spawn_new_synthesize(ctx.clone(), reusable_assembly, job_id, circuit);
-        scheduler_is_idle = std::time::Instant::now();
+        // hand jobs that never started back to the job manager
+        for (job_id, _) in pending_jobs.drain() {
+            ctx.report_sender
+                .send(JobResult::Failure(
+                    job_id,
+                    "prover service stopped before the job started".to_string(),
+                ))
+                .unwrap();
+            ctx.finish_job(job_id);
+        }
+    });
+
+    ShutdownHandle {
+        ctx,
+        scheduler,
+        polling_duration,
     }
 }
 
@@ -228,8 +649,8 @@ pub fn run_prover_with_remote_synthesizer<
     job_reporter: JR,
     circuit_ids: Option<Vec<u8>>,
     params: P,
-) {
-    let ctx = ProverContext::init(circuit_ids.clone(), params.number_of_parallel_synthesis());
+) -> ShutdownHandle {
+    let ctx = ProverContext::init(circuit_ids.clone(), &params);
     let ctx = Arc::new(ctx);
 
     let params = Arc::new(params);
@@ -249,35 +670,83 @@ pub fn run_prover_with_remote_synthesizer<
         circuit_ids,
         params.clone(),
     );
-    let mut scheduler_is_idle = std::time::Instant::now();
-    loop {
-        if let Some(mut encoded_assembly) = remote_synthesizer.try_next() {
-            let reusable_assembly = ctx.reusable_assembly_receiver.recv().unwrap();
-            let scheduler_received_input = scheduler_is_idle.elapsed();
-            ctx.report_sender
-                .send(JobResult::SchedulerWaitedIdle(scheduler_received_input))
-                .unwrap();
-            scheduler_is_idle = std::time::Instant::now();
-
-            let mut job_id_bytes = [0u8; 8];
-            encoded_assembly.read_exact(&mut job_id_bytes[..]).unwrap();
-            let job_id = usize::from_le_bytes(job_id_bytes);
-            let mut circuit_id = [0u8; 1];
-            encoded_assembly.read_exact(&mut circuit_id[..]).unwrap();
-            let circuit_id = circuit_id[0];
-            if check_job_is_allowed(ctx.as_ref(), job_id, circuit_id) == false {
-                continue;
+    let polling_duration = params.polling_duration();
+    let scheduler_ctx = ctx.clone();
+    let scheduler = std::thread::spawn(move || {
+        let ctx = scheduler_ctx;
+        ctx.wait_until_ready(polling_duration);
+        let mut scheduler_is_idle = std::time::Instant::now();
+        let mut advertised_free_slots = None;
+        while !ctx.is_stopping() {
+            ctx.poll_cancellations(|job_id| remote_synthesizer.is_cancelled(job_id));
+            // let remote synthesizers know how many more assemblies we can take
+            let free_slots = ctx.free_assemblies.load(Ordering::SeqCst);
+            if advertised_free_slots != Some(free_slots) {
+                remote_synthesizer.advertise_credits(Credits {
+                    free_slots,
+                    circuit_ids: ctx.specialized_circuit_ids.clone(),
+                });
+                advertised_free_slots = Some(free_slots);
+            }
+            // an encoding is only taken once there is an assembly to decode it into
+            let reusable_assembly = match ctx.try_take_reusable_assembly(polling_duration) {
+                Some(reusable_assembly) => reusable_assembly,
+                None => continue,
+            };
+            if let Some(encoded_assembly) = remote_synthesizer.try_next() {
+                let mut encoded_assembly = ChecksumReader::new(encoded_assembly);
+                let scheduler_received_input = scheduler_is_idle.elapsed();
+                ctx.report_sender
+                    .send(JobResult::SchedulerWaitedIdle(scheduler_received_input))
+                    .unwrap();
+                scheduler_is_idle = std::time::Instant::now();
+
+                let (header, job_id, circuit_id) =
+                    match deserialize_job_header(&mut encoded_assembly) {
+                        Ok(job_header) => job_header,
+                        Err(e) => {
+                            // there is no trustworthy job id to report against
+                            println!("rejected incompatible assembly encoding: {:?}", e);
+                            ctx.return_reusable_assembly(reusable_assembly);
+                            continue;
+                        }
+                    };
+                if check_job_is_allowed(ctx.as_ref(), job_id, circuit_id) == false {
+                    ctx.return_reusable_assembly(reusable_assembly);
+                    continue;
+                }
+                // resent by the synthesizer after the acknowledgement got lost
+                if ctx.is_in_flight(job_id) {
+                    println!("dropping duplicate encoding of job {}", job_id);
+                    ctx.return_reusable_assembly(reusable_assembly);
+                    continue;
+                }
+                let priority = remote_synthesizer.job_priority(job_id, circuit_id);
+                ctx.start_job(job_id, circuit_id, priority);
+                spawn_new_assembly_decoding(
+                    ctx.clone(),
+                    job_id,
+                    circuit_id,
+                    header,
+                    encoded_assembly,
+                    reusable_assembly,
+                );
+            } else {
+                ctx.return_reusable_assembly(reusable_assembly);
+                sleep_for_duration(polling_duration);
             }
-            spawn_new_assembly_decoding(
-                ctx.clone(),
-                job_id,
-                circuit_id,
-                encoded_assembly,
-                reusable_assembly,
-            );
-        } else {
-            sleep_for_duration(params.polling_duration());
         }
+        // don't let remote synthesizers send anything else
+        remote_synthesizer.advertise_credits(Credits {
+            free_slots: 0,
+            circuit_ids: ctx.specialized_circuit_ids.clone(),
+        });
+    });
+
+    ShutdownHandle {
+        ctx,
+        scheduler,
+        polling_duration,
     }
 }
 
@@ -297,9 +766,50 @@ fn thread_liveness_tracker<
     params: Arc<P>,
 ) {
     let duration = params.polling_duration();
-    std::thread::spawn(move || loop {
-        for report in ctx.report_receiver.try_iter() {
-            job_reporter.send_report(report);
+    let threads = ctx.clone();
+    threads.spawn_service(move || loop {
+        for event in ctx.report_receiver.try_iter() {
+            job_reporter.send_event(event);
+        }
+        if ctx.is_terminated() {
+            return;
+        }
+
+        for (job_id, stage, elapsed, prover_idx) in
+            ctx.take_expired_stages(|stage| params.stage_timeout(stage))
+        {
+            ctx.report_sender
+                .send(JobResult::Failure(
+                    job_id,
+                    format!("{:?} stage timed out after {:?}", stage, elapsed),
+                ))
+                .unwrap();
+            // the hung thread keeps its assembly, put a fresh one into the pool instead
+            if !ctx.replace_assembly(job_id) {
+                println!("no assembly replacement left for job {}", job_id);
+            }
+            // and keeps its worker busy
+            let stuck_pool = match stage {
+                JobStage::Synthesis => Some(WorkerPoolKind::Synthesis),
+                JobStage::Decoding => Some(WorkerPoolKind::Decoding),
+                // setup loader runs on its own thread unless a prover loads the setup
+                JobStage::SetupLoading if prover_idx.is_none() => None,
+                JobStage::SetupLoading | JobStage::Proving => Some(WorkerPoolKind::Proving),
+            };
+            if let Some(kind) = stuck_pool {
+                ctx.worker_pool(kind).replace_stuck_worker();
+            }
+            if let Some(prover_idx) = prover_idx {
+                println!("retiring prover {}, spawning a replacement", prover_idx);
+                let prover_ctx = ctx.clone();
+                ctx.spawn_service(move || {
+                    let prover = create_prover_instance(prover_idx);
+                    prover_ctx
+                        .prover_instance_sender
+                        .send((prover_idx, prover, std::time::Instant::now()))
+                        .unwrap();
+                });
+            }
         }
 
         for thread_id in ctx.thread_status_receiver.try_iter() {
@@ -329,16 +839,42 @@ fn spawn_new_assembly_decoding(
     ctx: Arc<ProverContext>,
     job_id: usize,
     circuit_id: u8,
-    mut encoded_assembly: Box<dyn Read + Send + Sync>,
+    header: EncodingHeader,
+    mut encoded_assembly: ChecksumReader<Box<dyn Read + Send + Sync>>,
     mut reusable_assembly: ProvingAssembly,
 ) {
     let log_degree = Prover::get_max_domain_size_log();
-    std::thread::spawn(move || {
+    let threads = ctx.clone();
+    threads.decoding_pool.execute(move || {
+        if ctx.is_aborted(job_id) {
+            ctx.abort_job(job_id, reusable_assembly);
+            return;
+        }
+        ctx.enter_stage(job_id, JobStage::Decoding, None);
         let assembly_decoded = std::time::Instant::now();
 
-        use super::remote_synth::custom_assembly_deserialization;
-        custom_assembly_deserialization(&mut encoded_assembly, &mut reusable_assembly);
+        let decoded = deserialize_sections(&header, &mut encoded_assembly, &mut reusable_assembly);
         drop(encoded_assembly);
+        if let Err(e) = decoded {
+            if e.is_corruption() {
+                // damaged in transit, the synthesizer can deliver it again
+                ctx.report_sender
+                    .send(JobResult::AssemblyCorrupted(job_id, format!("{:?}", e)))
+                    .unwrap();
+            } else {
+                ctx.report_final_failure(
+                    job_id,
+                    FailureClass::Decoding,
+                    JobResult::Failure(job_id, format!("assembly decoding failed: {:?}", e)),
+                );
+            }
+            ctx.release_job(job_id, recycle_assembly(reusable_assembly));
+            return;
+        }
+        if ctx.is_aborted(job_id) {
+            ctx.abort_job(job_id, reusable_assembly);
+            return;
+        }
 
         ctx.report_sender
             .send(JobResult::AssemblyDecoded(
@@ -355,6 +891,7 @@ fn spawn_new_assembly_decoding(
             ))
             .unwrap();
 
+        ctx.leave_stage(job_id);
         ctx.main_prover_input_sender
             .send(ProverMessage(reusable_assembly, job_id, circuit_id, None))
             .unwrap();
@@ -367,7 +904,8 @@ fn spawn_new_synthesize(
     job_id: usize,
     circuit: ZkSyncCircuit,
 ) {
-    std::thread::spawn(move || {
+    let threads = ctx.clone();
+    threads.synthesis_pool.execute(move || {
         let guard = ThreadGuard::new(
             SYNTH_THREAD_HANDLE,
             job_id,
@@ -375,13 +913,34 @@ fn spawn_new_synthesize(
             ctx.thread_status_sender.clone(),
         );
         let circuit_id = circuit.numeric_circuit_type();
+        if ctx.is_aborted(job_id) {
+            ctx.abort_job(job_id, assembly);
+            return;
+        }
         println!("synthesizing circuit {}", circuit.short_description());
+        ctx.enter_stage(job_id, JobStage::Synthesis, None);
 
         let synth_started = std::time::Instant::now();
-        circuit.synthesize(&mut assembly).unwrap();
+        let synthesized = catch_unwind(AssertUnwindSafe(|| {
+            circuit.synthesize(&mut assembly).unwrap()
+        }));
+        if synthesized.is_err() {
+            // the pool survives the panic, the assembly has to go back by hand
+            ctx.report_final_failure(
+                job_id,
+                FailureClass::Synthesis,
+                JobResult::Failure(job_id, "synthesize thread panicked".to_string()),
+            );
+            ctx.release_job(job_id, recycle_assembly(assembly));
+            return;
+        }
         ctx.report_sender
             .send(JobResult::Synthesized(job_id, synth_started.elapsed()))
             .unwrap();
+        if ctx.is_aborted(job_id) {
+            ctx.abort_job(job_id, assembly);
+            return;
+        }
 
         let assembly_finalized = std::time::Instant::now();
         let log_size = (prover::Prover::get_max_domain_size()).trailing_zeros();
@@ -393,79 +952,81 @@ fn spawn_new_synthesize(
             ))
             .unwrap();
 
-        let setup_loaded = std::time::Instant::now();
-
-        ctx.report_sender
-            .send(JobResult::SetupLoaded(job_id, setup_loaded.elapsed(), true))
-            .unwrap();
-
         let prover_input = ProverMessage(assembly, job_id, circuit_id, None);
+        ctx.leave_stage(job_id);
         ctx.main_prover_input_sender.send(prover_input).unwrap()
     });
 }
 
-fn init_setup_cache(
-    circuit_ids: Vec<u8>,
-    number_of_setup_slots: usize,
-) -> HashMap<u8, RwLock<Arc<ZkSyncSetup>>> {
-    println!("allocating {} setup buffers", circuit_ids.len());
-    assert!(circuit_ids.len() <= number_of_setup_slots);
-    let mut setup_map = HashMap::new();
-    for circuit_id in circuit_ids.iter() {
-        let setup = RwLock::new(Arc::new(ZkSyncSetup::empty(Prover::new_setup())));
-        setup_map.insert(*circuit_id, setup);
-    }
-    println!(
-        "all({} items) setup buffers are allocated",
-        circuit_ids.len()
-    );
-
-    setup_map
-}
-
 fn load_setup_or_read_from_cache<AM: ArtifactProvider>(
     ctx: Arc<ProverContext>,
-    cache: &HashMap<u8, RwLock<Arc<ZkSyncSetup>>>,
+    setup_cache: &Mutex<SetupCache>,
     circuit_id: u8,
     artifact_manager: Arc<AM>,
     job_id: usize,
-) -> Arc<ZkSyncSetup> {
-    let guarded_setup = cache.get(&circuit_id).expect("setup in cache");
-    let mut setup = guarded_setup.write().unwrap();
+    polling_duration: Duration,
+) -> Option<Arc<ZkSyncSetup>> {
     let setup_started = std::time::Instant::now();
-    if setup.is_free() {
-        println!("setup isn't in cache, loading.");
-        // setup initially contains dummy setup, so we need to load actual setup
-        let inner_setup = unsafe { Arc::get_mut_unchecked(&mut setup) };
-        if let Ok(setup_encoding) = artifact_manager.get_setup(circuit_id) {
-            inner_setup.reload(setup_encoding, circuit_id);
+    loop {
+        let mut cache = setup_cache.lock().unwrap();
+        if let Some(setup) = cache.get(circuit_id) {
+            // cache hit
             ctx.report_sender
                 .send(JobResult::SetupLoaded(
                     job_id,
                     setup_started.elapsed(),
-                    false,
+                    true,
                 ))
                 .unwrap();
+            return Some(setup);
+        }
+        // wait for another prover loading the same setup instead of loading it twice
+        let slot_idx = if cache.is_loading(circuit_id) {
+            None
         } else {
+            cache.reserve_slot()
+        };
+        if let Some(slot_idx) = slot_idx {
+            println!("setup isn't in cache, loading.");
+            let setup = cache.take_slot(slot_idx, circuit_id);
+            let mut setup = TakenSlot::new(setup_cache, slot_idx, setup);
+            // other provers use the cache while the setup is loaded
+            drop(cache);
+            let loaded = match artifact_manager.get_setup(circuit_id) {
+                Ok(setup_encoding) => {
+                    setup.setup_mut().reload(setup_encoding, circuit_id);
+                    true
+                }
+                Err(_) => false,
+            };
+            let mut cache = setup_cache.lock().unwrap();
+            let setup = setup.put_back(&mut cache);
+            ctx.set_resident_setups(cache.resident_circuits());
+            drop(cache);
+            if !loaded {
+                ctx.report_final_failure(
+                    job_id,
+                    FailureClass::MissingArtifact,
+                    JobResult::Failure(
+                        job_id,
+                        format!("setup encoding for circuit {} not found", circuit_id),
+                    ),
+                );
+                return None;
+            }
             ctx.report_sender
-                .send(JobResult::Failure(
+                .send(JobResult::SetupLoaded(
                     job_id,
-                    format!("setup encoding for circuit {} not found", circuit_id),
+                    setup_started.elapsed(),
+                    false,
                 ))
                 .unwrap();
+            return Some(setup);
         }
-    } else {
-        // cache hit
-        ctx.report_sender
-            .send(JobResult::SetupLoaded(
-                job_id,
-                setup_started.elapsed(),
-                true,
-            ))
-            .unwrap();
-    };
-
-    setup.clone()
+        drop(cache);
+        // every setup is in use by another prover or being loaded
+        sleep_for_duration(polling_duration);
+    }
 }
 
 struct ProverMessage(ProvingAssembly, usize, u8, Option<Arc<ZkSyncSetup>>);
@@ -495,123 +1056,111 @@ fn setup_loader<AM: ArtifactProvider + 'static, P: Params>(
     artifact_manager: Arc<AM>,
     params: Arc<P>,
 ) {
-    let num_setup_slots = params.number_of_setup_slots();
-
-    std::thread::spawn(move || loop {
-        let mut cache: Vec<Arc<ZkSyncSetup>> = (0..num_setup_slots)
-            .map(|_| Arc::new(ZkSyncSetup::empty(Prover::new_setup())))
-            .collect::<Vec<Arc<ZkSyncSetup>>>();
+    let num_setup_slots = params.number_of_setup_slots() as usize;
+    let polling_duration = params.polling_duration();
+    let setup_cache_policy = params.setup_cache_policy();
+    let preload_circuits = params.preload_circuits();
+
+    let threads = ctx.clone();
+    threads.spawn_service(move || {
+        let warm_up_started = std::time::Instant::now();
+        let mut cache = SetupCache::new(num_setup_slots, setup_cache_policy);
+        // a respawned handler loads setups on demand
+        if !ctx.is_ready() {
+            cache.preload(&preload_circuits, artifact_manager.as_ref());
+        }
+        ctx.set_resident_setups(cache.resident_circuits());
+        ctx.mark_ready(warm_up_started.elapsed());
         println!("setup handler started");
 
         let mut setup_loader_is_idle = std::time::Instant::now();
 
         'outer: loop {
-            if let Ok(setup_input) = ctx
+            if ctx.is_terminated() {
+                return;
+            }
+            let setup_input = match ctx
                 .setup_input_receiver
                 .as_ref()
                 .expect("setup receiver")
-                .recv()
+                .recv_timeout(polling_duration)
             {
-                let setup_input_received = setup_loader_is_idle.elapsed();
-                ctx.report_sender
-                    .send(JobResult::SetupLoaderWaitedIdle(setup_input_received))
-                    .unwrap();
+                Ok(setup_input) => setup_input,
+                Err(_) => continue,
+            };
+            let setup_input_received = setup_loader_is_idle.elapsed();
+            ctx.report_sender
+                .send_with_job(
+                    setup_input.1,
+                    JobResult::SetupLoaderWaitedIdle(setup_input_received),
+                )
+                .unwrap();
 
-                let ProverMessage(assembly, job_id, circuit_id, _) = setup_input;
-                let guard = ThreadGuard::new(
-                    SETUP_THREAD_HANDLE,
-                    job_id,
-                    ctx.report_sender.clone(),
-                    ctx.thread_status_sender.clone(),
-                );
+            let ProverMessage(assembly, job_id, circuit_id, _) = setup_input;
+            if ctx.is_aborted(job_id) {
+                ctx.abort_job(job_id, assembly);
+                setup_loader_is_idle = std::time::Instant::now();
+                continue 'outer;
+            }
+            ctx.enter_stage(job_id, JobStage::SetupLoading, None);
+            let guard = ThreadGuard::new(
+                SETUP_THREAD_HANDLE,
+                job_id,
+                ctx.report_sender.clone(),
+                ctx.thread_status_sender.clone(),
+            );
 
-                let mut assembly = Some(assembly);
-
-                let setup_started = std::time::Instant::now();
-                loop {
-                    // first try to hit cache
-                    let mut cache_hit = false;
-                    'inner: for entry in cache.iter() {
-                        if entry.is_busy() {
-                            if entry.numeric_circuit_type() == circuit_id {
-                                ctx.prover_input_sender
-                                    .send(ProverMessage(
-                                        assembly.take().unwrap(),
-                                        job_id,
-                                        circuit_id,
-                                        Some(entry.clone()),
-                                    ))
-                                    .unwrap();
-                                cache_hit = true;
-                                break 'inner;
-                            }
-                        }
+            let setup_started = std::time::Instant::now();
+            let (setup, cache_hit) = loop {
+                if ctx.is_terminated() {
+                    return;
+                }
+                // first try to hit cache
+                if let Some(setup) = cache.get(circuit_id) {
+                    break (setup, true);
+                }
+                // then look for a free slot or a setup the policy lets go
+                let slot_idx = match cache.reserve_slot() {
+                    Some(slot_idx) => slot_idx,
+                    None => {
+                        // every setup is in use by a prover
+                        sleep_for_duration(polling_duration);
+                        continue;
                     }
-
-                    // invalidate stale entries
-                    let mut invalidated = false;
-                    for slot in cache.iter_mut() {
-                        if invalidated {
-                            continue;
-                        }
-                        if slot.is_busy() {
-                            if Arc::strong_count(slot) == 1 {
-                                let inner = unsafe { Arc::get_mut_unchecked(slot) };
-                                inner.free();
-                                invalidated = true;
-                            }
-                        }
+                };
+                match artifact_manager.get_setup(circuit_id) {
+                    Ok(setup_encoding) => {
+                        let setup = cache.load(slot_idx, circuit_id, setup_encoding);
+                        assert_eq!(setup.numeric_circuit_type(), circuit_id);
+                        break (setup, false);
                     }
-
-                    if cache_hit {
-                        ctx.report_sender
-                            .send(JobResult::SetupLoaded(
-                                job_id,
-                                setup_started.elapsed(),
-                                cache_hit,
-                            ))
-                            .unwrap();
+                    Err(e) => {
+                        ctx.report_final_failure(
+                            job_id,
+                            FailureClass::MissingArtifact,
+                            JobResult::Failure(job_id, e.to_string()),
+                        );
+                        ctx.release_job(job_id, recycle_assembly(assembly));
                         setup_loader_is_idle = std::time::Instant::now();
                         continue 'outer;
                     }
-
-                    // then look for free slots
-                    for slot in cache.iter_mut() {
-                        if slot.is_free() {
-                            match artifact_manager.get_setup(circuit_id) {
-                                Ok(setup_encoding) => {
-                                    let inner = unsafe { Arc::get_mut_unchecked(slot) };
-                                    inner.reload(setup_encoding, circuit_id);
-                                    assert_eq!(inner.numeric_circuit_type(), circuit_id);
-                                    ctx.prover_input_sender
-                                        .send(ProverMessage(
-                                            assembly.take().unwrap(),
-                                            job_id,
-                                            circuit_id,
-                                            Some(slot.clone()),
-                                        ))
-                                        .unwrap();
-                                    ctx.report_sender
-                                        .send(JobResult::SetupLoaded(
-                                            job_id,
-                                            setup_started.elapsed(),
-                                            false,
-                                        ))
-                                        .unwrap();
-                                    setup_loader_is_idle = std::time::Instant::now();
-                                    continue 'outer;
-                                }
-                                Err(e) => {
-                                    ctx.report_sender
-                                        .send(JobResult::Failure(job_id, e.to_string()))
-                                        .unwrap();
-                                }
-                            }
-                        }
-                    }
-                    setup_loader_is_idle = std::time::Instant::now();
                 }
-            }
+            };
+            ctx.set_resident_setups(cache.resident_circuits());
+
+            ctx.leave_stage(job_id);
+            ctx.prover_input_sender
+                .send(ProverMessage(assembly, job_id, circuit_id, Some(setup)))
+                .unwrap();
+            ctx.report_sender
+                .send(JobResult::SetupLoaded(
+                    job_id,
+                    setup_started.elapsed(),
+                    cache_hit,
+                ))
+                .unwrap();
+            drop(guard);
+            setup_loader_is_idle = std::time::Instant::now();
         }
     });
 }
@@ -624,41 +1173,77 @@ fn proof_handler<AM: ArtifactProvider + 'static, P: Params>(
 ) {
     let polling_duration = params.polling_duration();
     let number_of_setup_slots = params.number_of_setup_slots() as usize;
+    let priority_aging_interval = params.priority_aging_interval();
+    let setup_cache_policy = params.setup_cache_policy();
+    let preload_circuits = params.preload_circuits();
+    let retry_policy = params.retry_policy();
 
-    std::thread::spawn(move || {
+    let threads = ctx.clone();
+    threads.spawn_service(move || {
         let setup_cache = if let Some(circuit_ids) = circuit_ids {
-            let cache = init_setup_cache(circuit_ids, number_of_setup_slots);
-            let cache = Arc::new(cache);
+            let warm_up_started = std::time::Instant::now();
+            // specialized provers need no more buffers than circuit types
+            let num_slots = circuit_ids.len().min(number_of_setup_slots);
+            let mut cache = SetupCache::new(num_slots, setup_cache_policy);
+            let preload_circuits: Vec<u8> = preload_circuits
+                .into_iter()
+                .filter(|circuit_id| circuit_ids.contains(circuit_id))
+                .collect();
+            // a respawned handler loads setups on demand
+            if !ctx.is_ready() {
+                cache.preload(&preload_circuits, artifact_manager.as_ref());
+            }
+            ctx.set_resident_setups(cache.resident_circuits());
+            ctx.mark_ready(warm_up_started.elapsed());
+            let cache = Arc::new(Mutex::new(cache));
             Some(cache)
         } else {
             None
         };
 
-        loop {
+        let mut pending_inputs = PriorityQueue::new(priority_aging_interval);
+        while !ctx.is_terminated() {
             let artifact_manager = artifact_manager.clone();
-            let job_reporter = ctx.report_sender.clone();
-            let reusable_assembly_sender = ctx.reusable_assembly_sender.clone();
-            let prover_instance_sender = ctx.prover_instance_sender.clone();
             let setup_cache = setup_cache.clone();
 
+            // every ready input competes for the next free prover
+            for input in ctx.prover_input_receiver.try_iter() {
+                let priority = ctx.job_priority(input.1);
+                pending_inputs.push(input, priority);
+            }
+            if pending_inputs.is_empty() {
+                if let Ok(input) = ctx.prover_input_receiver.recv_timeout(polling_duration) {
+                    let priority = ctx.job_priority(input.1);
+                    pending_inputs.push(input, priority);
+                }
+                continue;
+            }
+
             if let Ok((prover_idx, prover, prover_instance_become_idle)) =
-                ctx.prover_instance_receiver.try_recv()
+                ctx.prover_instance_receiver.recv_timeout(polling_duration)
             {
-                let input = ctx.prover_input_receiver.recv().unwrap();
+                let input = pending_inputs.pop().unwrap();
                 let prover = (prover_idx, prover);
                 let prover_input_received = prover_instance_become_idle.elapsed();
                 ctx.report_sender
-                    .send(JobResult::ProverWaitedIdle(
-                        prover_idx,
-                        prover_input_received,
-                    ))
+                    .send_with_job(
+                        input.1,
+                        JobResult::ProverWaitedIdle(prover_idx, prover_input_received),
+                    )
                     .unwrap();
-                let ctx = ctx.clone();
-                std::thread::spawn(move || {
-                    create_proof(ctx, artifact_manager, input, prover, setup_cache)
+                let job_ctx = ctx.clone();
+                let retry_policy = retry_policy.clone();
+                ctx.proving_pool.execute(move || {
+                    create_proof(
+                        job_ctx,
+                        artifact_manager,
+                        input,
+                        prover,
+                        setup_cache,
+                        polling_duration,
+                        retry_policy,
+                    )
                 });
-            } else {
-                sleep_for_duration(polling_duration);
             }
         }
     });
@@ -669,7 +1254,9 @@ fn create_proof<AM: ArtifactProvider>(
     artifact_manager: Arc<AM>,
     input: ProverMessage,
     mut prover: (usize, Prover),
-    setup_cache: Option<Arc<HashMap<u8, RwLock<Arc<ZkSyncSetup>>>>>,
+    setup_cache: Option<Arc<Mutex<SetupCache>>>,
+    polling_duration: Duration,
+    retry_policy: RetryPolicy,
 ) {
     let job_id = input.1;
     let guard = ThreadGuard::new(
@@ -680,22 +1267,42 @@ fn create_proof<AM: ArtifactProvider>(
     );
 
     let ProverMessage(assembly, job_id, circuit_id, mut setup) = input;
+    let (prover_idx, mut prover) = prover;
+
+    if ctx.is_aborted(job_id) {
+        ctx.prover_instance_sender
+            .send((prover_idx, prover, std::time::Instant::now()))
+            .unwrap();
+        ctx.abort_job(job_id, assembly);
+        return;
+    }
 
     let setup = if let Some(setup_cache) = setup_cache {
-        load_setup_or_read_from_cache(
+        ctx.enter_stage(job_id, JobStage::SetupLoading, Some(prover_idx));
+        let setup = load_setup_or_read_from_cache(
             ctx.clone(),
             setup_cache.as_ref(),
             circuit_id,
             artifact_manager.clone(),
             job_id,
-        )
+            polling_duration,
+        );
+        match setup {
+            Some(setup) => setup,
+            None => {
+                ctx.prover_instance_sender
+                    .send((prover_idx, prover, std::time::Instant::now()))
+                    .unwrap();
+                ctx.release_job(job_id, recycle_assembly(assembly));
+                return;
+            }
+        }
     } else {
         setup.take().unwrap()
     };
 
-    let (prover_idx, mut prover) = prover;
-
-    let report = if let Ok(vk) = artifact_manager.get_vk(circuit_id) {
+    ctx.enter_stage(job_id, JobStage::Proving, Some(prover_idx));
+    let (mut report, failure_class) = if let Ok(vk) = artifact_manager.get_vk(circuit_id) {
         let proof_generated = std::time::Instant::now();
         println!("Creating proof for job-id: {}", job_id);
         let result = if circuit_id == 0 {
@@ -711,38 +1318,90 @@ fn create_proof<AM: ArtifactProvider>(
             Ok(proof) => {
                 let proof = ZkSyncProof::from_proof_and_numeric_type(circuit_id, proof);
                 if vk.verify_proof(&proof) {
-                    JobResult::ProofGenerated(job_id, proof_generated, proof, prover_idx)
+                    (
+                        JobResult::ProofGenerated(job_id, proof_generated, proof, prover_idx),
+                        None,
+                    )
                 } else {
-                    JobResult::Failure(job_id, format!("{} proof verification failed", prover_idx))
+                    (
+                        JobResult::Failure(
+                            job_id,
+                            format!("{} proof verification failed", prover_idx),
+                        ),
+                        Some(FailureClass::Verification),
+                    )
                 }
             }
-            Err(msg) => {
-                // JobResult::Failure(job_id, format!("proof generation failed: {}", msg))
+            // the assembly is attached for debugging once the job isn't retried anymore
+            Err(msg) => (
+                JobResult::Failure(
+                    job_id,
+                    format!("{} proof generation failed: {}", prover_idx, msg),
+                ),
+                Some(FailureClass::Proving),
+            ),
+        }
+    } else {
+        (
+            JobResult::Failure(job_id, format!("{} couldn't get a vk", prover_idx)),
+            Some(FailureClass::MissingArtifact),
+        )
+    };
+
+    let timed_out = ctx.is_timed_out(job_id);
+    if timed_out {
+        // the watchdog has spawned a replacement and reported the failure already
+        println!("dropping retired prover {}", prover_idx);
+        drop(prover);
+    } else {
+        ctx.prover_instance_sender
+            .send((prover_idx, prover, std::time::Instant::now()))
+            .unwrap();
+        if let Some(failure_class) = failure_class {
+            let attempt = ctx.record_failed_attempt(job_id);
+            if retry_policy.should_retry(failure_class, attempt) {
+                let reason = match &report {
+                    JobResult::Failure(_, msg) => msg.clone(),
+                    _ => unreachable!(),
+                };
+                ctx.report_sender
+                    .send(JobResult::RetryScheduled(job_id, attempt, reason))
+                    .unwrap();
+                let backoff = retry_policy.backoff(attempt);
+                ctx.leave_stage(job_id);
+                // the job keeps its assembly while it waits for the next attempt
+                let retry_ctx = ctx.clone();
+                ctx.spawn_job(move || {
+                    sleep_for_duration(backoff);
+                    retry_ctx.job_contexts.set_attempt(job_id, attempt + 1);
+                    retry_ctx
+                        .prover_input_sender
+                        .send(ProverMessage(assembly, job_id, circuit_id, Some(setup)))
+                        .unwrap();
+                });
+                return;
+            }
+            ctx.report_sender
+                .send(JobResult::RetriesExhausted(job_id, attempt))
+                .unwrap();
+            if let (FailureClass::Proving, JobResult::Failure(_, msg)) = (failure_class, &report) {
                 let mut assembly_encoding =
                     Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
                 serialize_job(&assembly, job_id, circuit_id, &mut assembly_encoding);
-                JobResult::FailureWithDebugging(
+                report = JobResult::FailureWithDebugging(
                     job_id,
                     circuit_id,
                     assembly_encoding,
-                    format!("{} proof generation failed: {}", prover_idx, msg),
-                )
+                    msg.clone(),
+                );
             }
         }
-    } else {
-        JobResult::Failure(job_id, format!("{} couldn't get a vk", prover_idx))
-    };
-
-    ctx.prover_instance_sender
-        .send((prover_idx, prover, std::time::Instant::now()))
-        .unwrap();
+        // report first so that a drained service has nothing left to report
+        ctx.report_sender.send(report).unwrap();
+    }
 
     let recycled_assembly = recycle_assembly(assembly);
-    ctx.reusable_assembly_sender
-        .send(recycled_assembly)
-        .unwrap();
-
-    ctx.report_sender.send(report).unwrap();
+    ctx.release_job(job_id, recycled_assembly);
 }
 
 pub(crate) fn recycle_assembly(assembly: ProvingAssembly) -> ProvingAssembly {
@@ -802,8 +1461,20 @@ pub(crate) fn create_prover_instances() -> Vec<(usize, Prover)> {
     vec![(0, Prover::new())]
 }
 
+#[cfg(feature = "legacy")]
+pub(crate) fn create_prover_instance(_prover_idx: usize) -> Prover {
+    Prover::new()
+}
+
+/// The legacy prover runs on the cpu
+#[cfg(feature = "legacy")]
+fn prover_device_ids() -> Vec<Vec<usize>> {
+    vec![vec![]]
+}
+
+/// Devices of every prover instance
 #[cfg(not(feature = "legacy"))]
-pub(crate) fn create_prover_instances() -> Vec<(usize, Prover)> {
+fn prover_device_ids() -> Vec<Vec<usize>> {
     let actual_num_gpus = prover::gpu_prover::cuda_bindings::devices().unwrap() as usize;
 
     let info = prover::gpu_prover::cuda_bindings::device_info(0).unwrap();
@@ -814,11 +1485,19 @@ pub(crate) fn create_prover_instances() -> Vec<(usize, Prover)> {
     dbg!(available_memory);
     println!("actual num gpus: {}", actual_num_gpus);
 
+    (0..actual_num_gpus / num_gpus_per_prover_instance)
+        .map(|idx| {
+            let start = num_gpus_per_prover_instance * idx;
+            let end = start + num_gpus_per_prover_instance;
+            (start..end).collect()
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "legacy"))]
+pub(crate) fn create_prover_instances() -> Vec<(usize, Prover)> {
     let mut prover_instances = vec![];
-    for idx in 0..actual_num_gpus / num_gpus_per_prover_instance {
-        let start = num_gpus_per_prover_instance * idx;
-        let end = start + num_gpus_per_prover_instance;
-        let device_ids: Vec<usize> = (start..end).collect();
+    for (idx, device_ids) in prover_device_ids().into_iter().enumerate() {
         println!("loading prover {}", idx);
         prover_instances.push((idx, Prover::new_gpu_with_affinity(&device_ids)));
     }
@@ -827,3 +1506,10 @@ pub(crate) fn create_prover_instances() -> Vec<(usize, Prover)> {
 
     prover_instances
 }
+
+#[cfg(not(feature = "legacy"))]
+pub(crate) fn create_prover_instance(prover_idx: usize) -> Prover {
+    let device_ids = &prover_device_ids()[prover_idx];
+    println!("loading prover {} on devices {:?}", prover_idx, device_ids);
+    Prover::new_gpu_with_affinity(device_ids)
+}
@@ -0,0 +1,91 @@
+use std::time::Instant;
+
+use super::*;
+
+/// Inputs waiting for a free resource, served by priority. Every full aging
+/// interval an input waits raises its priority by one so that low priority
+/// inputs can't starve, inputs of equal priority are served in arrival order.
+pub(crate) struct PriorityQueue<T> {
+    // item, priority, arrival and how many times it was passed over
+    entries: Vec<(T, JobPriority, Instant, usize)>,
+    aging_interval: Duration,
+}
+
+impl<T> PriorityQueue<T> {
+    pub(crate) fn new(aging_interval: Duration) -> Self {
+        Self {
+            entries: vec![],
+            aging_interval,
+        }
+    }
+
+    pub(crate) fn push(&mut self, item: T, priority: JobPriority) {
+        self.entries.push((item, priority, Instant::now(), 0));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn effective_priority(&self, priority: JobPriority, enqueued: Instant) -> JobPriority {
+        if self.aging_interval.is_zero() {
+            return priority;
+        }
+        let levels = enqueued.elapsed().as_nanos() / self.aging_interval.as_nanos();
+        priority.saturating_add(levels.min(JobPriority::MAX as u128) as JobPriority)
+    }
+
+    fn top(&self) -> Option<(usize, JobPriority)> {
+        let mut selected: Option<(usize, JobPriority)> = None;
+        for (idx, (_, priority, enqueued, _)) in self.entries.iter().enumerate() {
+            let priority = self.effective_priority(*priority, *enqueued);
+            // strictly greater keeps the oldest of equal priorities
+            if selected.map_or(true, |(_, selected_priority)| priority > selected_priority) {
+                selected = Some((idx, priority));
+            }
+        }
+
+        selected
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<T> {
+        let (idx, _) = self.top()?;
+
+        Some(self.entries.remove(idx).0)
+    }
+
+    /// Like `pop` but serves a preferred input of the same priority ahead of the
+    /// top one, the top input is passed over at most `max_skips` times.
+    pub(crate) fn pop_preferring<F: Fn(&T) -> bool>(
+        &mut self,
+        is_preferred: F,
+        max_skips: usize,
+    ) -> Option<T> {
+        let (top_idx, top_priority) = self.top()?;
+        let (top_item, _, _, skips) = &self.entries[top_idx];
+        if *skips >= max_skips || is_preferred(top_item) {
+            return Some(self.entries.remove(top_idx).0);
+        }
+        let preferred_idx = self
+            .entries
+            .iter()
+            .position(|(item, priority, enqueued, _)| {
+                self.effective_priority(*priority, *enqueued) == top_priority && is_preferred(item)
+            });
+        match preferred_idx {
+            Some(idx) => {
+                self.entries[top_idx].3 += 1;
+                Some(self.entries.remove(idx).0)
+            }
+            None => Some(self.entries.remove(top_idx).0),
+        }
+    }
+
+    pub(crate) fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        self.entries.drain(..).map(|(item, _, _, _)| item)
+    }
+}
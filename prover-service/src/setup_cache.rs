@@ -0,0 +1,303 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::setup::ZkSyncSetup;
+
+use super::*;
+
+/// Decides which loaded setup gives way when every setup slot is taken.
+pub trait SetupCachePolicy: Send {
+    /// Called whenever a job uses the resident setup of the given circuit type.
+    fn on_access(&mut self, circuit_id: u8);
+    /// Called after the setup of the given circuit type is loaded into a slot.
+    fn on_load(&mut self, circuit_id: u8) {
+        self.on_access(circuit_id)
+    }
+    /// Called after the setup of the given circuit type is evicted.
+    fn on_evict(&mut self, circuit_id: u8);
+    /// Picks the setup to evict among the ones no job uses right now,
+    /// `None` keeps all of them resident.
+    fn select_victim(&mut self, candidates: &[u8]) -> Option<u8>;
+}
+
+/// Evicts the setup that was used least recently.
+#[derive(Default)]
+pub struct LruPolicy {
+    clock: u64,
+    last_used: HashMap<u8, u64>,
+}
+
+impl SetupCachePolicy for LruPolicy {
+    fn on_access(&mut self, circuit_id: u8) {
+        self.clock += 1;
+        self.last_used.insert(circuit_id, self.clock);
+    }
+
+    fn on_evict(&mut self, circuit_id: u8) {
+        self.last_used.remove(&circuit_id);
+    }
+
+    fn select_victim(&mut self, candidates: &[u8]) -> Option<u8> {
+        candidates
+            .iter()
+            .min_by_key(|circuit_id| self.last_used.get(circuit_id).copied().unwrap_or(0))
+            .copied()
+    }
+}
+
+/// Evicts the setup that was used least often since it was loaded,
+/// ties go to the least recently used one.
+#[derive(Default)]
+pub struct LfuPolicy {
+    clock: u64,
+    uses: HashMap<u8, (u64, u64)>,
+}
+
+impl SetupCachePolicy for LfuPolicy {
+    fn on_access(&mut self, circuit_id: u8) {
+        self.clock += 1;
+        let uses = self.uses.entry(circuit_id).or_insert((0, 0));
+        uses.0 += 1;
+        uses.1 = self.clock;
+    }
+
+    fn on_evict(&mut self, circuit_id: u8) {
+        self.uses.remove(&circuit_id);
+    }
+
+    fn select_victim(&mut self, candidates: &[u8]) -> Option<u8> {
+        candidates
+            .iter()
+            .min_by_key(|circuit_id| self.uses.get(circuit_id).copied().unwrap_or((0, 0)))
+            .copied()
+    }
+}
+
+/// Never evicts the setups of the pinned circuit types and leaves every other
+/// decision to the inner policy. Pinned circuits must leave at least one slot
+/// for the rest, otherwise their jobs wait forever.
+pub struct PinnedPolicy {
+    pinned: HashSet<u8>,
+    inner: Box<dyn SetupCachePolicy>,
+}
+
+impl PinnedPolicy {
+    pub fn new(pinned: HashSet<u8>, inner: Box<dyn SetupCachePolicy>) -> Self {
+        Self { pinned, inner }
+    }
+}
+
+impl SetupCachePolicy for PinnedPolicy {
+    fn on_access(&mut self, circuit_id: u8) {
+        self.inner.on_access(circuit_id)
+    }
+
+    fn on_load(&mut self, circuit_id: u8) {
+        self.inner.on_load(circuit_id)
+    }
+
+    fn on_evict(&mut self, circuit_id: u8) {
+        self.inner.on_evict(circuit_id)
+    }
+
+    fn select_victim(&mut self, candidates: &[u8]) -> Option<u8> {
+        let candidates: Vec<u8> = candidates
+            .iter()
+            .copied()
+            .filter(|circuit_id| !self.pinned.contains(circuit_id))
+            .collect();
+        self.inner.select_victim(&candidates)
+    }
+}
+
+/// Fixed number of setup buffers shared by all circuit types. A setup can only
+/// be evicted once no job holds it anymore.
+pub(crate) struct SetupCache {
+    // `None` while the setup of the slot is loaded outside the cache
+    slots: Vec<Option<Arc<ZkSyncSetup>>>,
+    loading: HashMap<usize, u8>,
+    policy: Box<dyn SetupCachePolicy>,
+}
+
+impl SetupCache {
+    pub(crate) fn new(num_slots: usize, policy: Box<dyn SetupCachePolicy>) -> Self {
+        println!("allocating {} setup buffers", num_slots);
+        let slots = (0..num_slots)
+            .map(|_| Some(Arc::new(ZkSyncSetup::empty(Prover::new_setup()))))
+            .collect();
+
+        Self {
+            slots,
+            loading: HashMap::new(),
+            policy,
+        }
+    }
+
+    /// Resident setup of the given circuit type
+    pub(crate) fn get(&mut self, circuit_id: u8) -> Option<Arc<ZkSyncSetup>> {
+        let slot = self
+            .slots
+            .iter()
+            .flatten()
+            .find(|slot| slot.is_busy() && slot.numeric_circuit_type() == circuit_id)?;
+        self.policy.on_access(circuit_id);
+
+        Some(slot.clone())
+    }
+
+    /// Returns a free slot, evicting a setup chosen by the policy if there is none.
+    /// `None` means that every setup is in use or kept by the policy.
+    pub(crate) fn reserve_slot(&mut self) -> Option<usize> {
+        if let Some(slot_idx) = self.free_slot() {
+            return Some(slot_idx);
+        }
+        let candidates: Vec<u8> = self
+            .slots
+            .iter()
+            .flatten()
+            .filter(|slot| Arc::strong_count(slot) == 1)
+            .map(|slot| slot.numeric_circuit_type())
+            .collect();
+        let victim = self.policy.select_victim(&candidates)?;
+        let slot_idx = self.slots.iter().position(|slot| {
+            slot.as_ref().map_or(false, |slot| {
+                Arc::strong_count(slot) == 1 && slot.numeric_circuit_type() == victim
+            })
+        })?;
+        self.slot_mut(slot_idx)
+            .expect("evicted setup is in use")
+            .free();
+        self.policy.on_evict(victim);
+        println!("evicted setup of circuit {}", victim);
+
+        Some(slot_idx)
+    }
+
+    pub(crate) fn load(
+        &mut self,
+        slot_idx: usize,
+        circuit_id: u8,
+        encoding: Box<dyn Read>,
+    ) -> Arc<ZkSyncSetup> {
+        self.slot_mut(slot_idx)
+            .expect("reserved setup slot is in use")
+            .reload(encoding, circuit_id);
+        self.policy.on_load(circuit_id);
+
+        self.slots[slot_idx].clone().expect("setup slot is taken")
+    }
+
+    /// Takes the setup of a reserved slot out of the cache so that the given
+    /// circuit can be loaded into it without holding the cache, the setup has
+    /// to be handed back with `put_back`, see `TakenSlot`.
+    pub(crate) fn take_slot(&mut self, slot_idx: usize, circuit_id: u8) -> ZkSyncSetup {
+        let slot = self.slots[slot_idx].take().expect("setup slot is taken");
+        self.loading.insert(slot_idx, circuit_id);
+
+        Arc::try_unwrap(slot)
+            .ok()
+            .expect("reserved setup slot is in use")
+    }
+
+    /// Whether the setup of the given circuit type is being loaded outside the cache
+    pub(crate) fn is_loading(&self, circuit_id: u8) -> bool {
+        self.loading.values().any(|loading| *loading == circuit_id)
+    }
+
+    pub(crate) fn put_back(&mut self, slot_idx: usize, setup: ZkSyncSetup) -> Arc<ZkSyncSetup> {
+        self.loading.remove(&slot_idx);
+        if setup.is_busy() {
+            self.policy.on_load(setup.numeric_circuit_type());
+        }
+        let setup = Arc::new(setup);
+        self.slots[slot_idx] = Some(setup.clone());
+
+        setup
+    }
+
+    fn free_slot(&self) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.as_ref().map_or(false, |slot| slot.is_free()))
+    }
+
+    fn slot_mut(&mut self, slot_idx: usize) -> Option<&mut ZkSyncSetup> {
+        self.slots[slot_idx].as_mut().and_then(Arc::get_mut)
+    }
+
+    /// Loads the setups of the given circuit types into free slots without evicting anything
+    pub(crate) fn preload<AM: ArtifactProvider>(
+        &mut self,
+        circuit_ids: &[u8],
+        artifact_manager: &AM,
+    ) {
+        for circuit_id in circuit_ids.iter().copied() {
+            if self.get(circuit_id).is_some() {
+                continue;
+            }
+            let slot_idx = match self.free_slot() {
+                Some(slot_idx) => slot_idx,
+                None => {
+                    println!("no free setup slot left to preload circuit {}", circuit_id);
+                    return;
+                }
+            };
+            match artifact_manager.get_setup(circuit_id) {
+                Ok(setup_encoding) => {
+                    self.load(slot_idx, circuit_id, setup_encoding);
+                    println!("preloaded setup of circuit {}", circuit_id);
+                }
+                Err(e) => println!(
+                    "failed preloading setup of circuit {}: {}",
+                    circuit_id,
+                    e.to_string()
+                ),
+            }
+        }
+    }
+
+    pub(crate) fn resident_circuits(&self) -> impl Iterator<Item = u8> + '_ {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|slot| slot.is_busy())
+            .map(|slot| slot.numeric_circuit_type())
+    }
+}
+
+/// Setup taken out of the cache, it goes back as a free slot when the guard is
+/// dropped without `put_back`, e.g. because loading it panicked.
+pub(crate) struct TakenSlot<'a> {
+    cache: &'a Mutex<SetupCache>,
+    slot_idx: usize,
+    setup: Option<ZkSyncSetup>,
+}
+
+impl<'a> TakenSlot<'a> {
+    pub(crate) fn new(cache: &'a Mutex<SetupCache>, slot_idx: usize, setup: ZkSyncSetup) -> Self {
+        Self {
+            cache,
+            slot_idx,
+            setup: Some(setup),
+        }
+    }
+
+    pub(crate) fn setup_mut(&mut self) -> &mut ZkSyncSetup {
+        self.setup.as_mut().expect("setup is put back")
+    }
+
+    pub(crate) fn put_back(mut self, cache: &mut SetupCache) -> Arc<ZkSyncSetup> {
+        let setup = self.setup.take().expect("setup is put back");
+        cache.put_back(self.slot_idx, setup)
+    }
+}
+
+impl Drop for TakenSlot<'_> {
+    fn drop(&mut self) {
+        if let Some(mut setup) = self.setup.take() {
+            // whatever got loaded is incomplete
+            setup.free();
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            cache.put_back(self.slot_idx, setup);
+        }
+    }
+}
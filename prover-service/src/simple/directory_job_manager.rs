@@ -0,0 +1,277 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Instant, SystemTime},
+};
+
+use super::*;
+
+pub const DONE_DIR: &str = "done";
+pub const FAILED_DIR: &str = "failed";
+
+struct DropFolder {
+    dir: PathBuf,
+    poll_interval: Duration,
+    last_scan: Option<Instant>,
+    next_job_id: JobId,
+    // every circuit file queued so far, kept until it is moved away
+    seen: HashSet<PathBuf>,
+    // circuit files are decoded when a job is taken
+    pending: VecDeque<PathBuf>,
+    // circuits decoded while looking for another circuit type
+    decoded: VecDeque<(PathBuf, ZkSyncCircuit)>,
+    in_flight: HashMap<JobId, PathBuf>,
+    // jobs the service gave up on, their last failure is reported next
+    exhausted: HashSet<JobId>,
+}
+
+impl DropFolder {
+    fn is_circuit_file(path: &Path) -> bool {
+        let file_name = match path.file_name() {
+            Some(file_name) => file_name.to_string_lossy(),
+            None => return false,
+        };
+        file_name.starts_with("circuit")
+            && (file_name.ends_with(".bin") || file_name.ends_with(".json"))
+    }
+
+    /// Queues the circuit files that appeared since the last scan, they count as
+    /// seen from now on. Files modified within the last poll interval may still be
+    /// written and are picked up by a later scan.
+    fn scan(&mut self) {
+        if self
+            .last_scan
+            .map_or(false, |last_scan| last_scan.elapsed() < self.poll_interval)
+        {
+            return;
+        }
+        self.last_scan = Some(Instant::now());
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                println!("failed reading drop folder {:?}: {}", self.dir, e);
+                return;
+            }
+        };
+        let mut paths: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().map_or(false, |kind| kind.is_file()))
+            .map(|entry| entry.path())
+            .filter(|path| Self::is_circuit_file(path) && !self.seen.contains(path))
+            .filter(|path| {
+                std::fs::metadata(path)
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+                    .map_or(false, |age| age >= self.poll_interval)
+            })
+            .collect();
+        // files are served in the order of their names
+        paths.sort();
+        self.seen.extend(paths.iter().cloned());
+        self.pending.extend(paths);
+    }
+
+    fn start_job(&mut self, path: PathBuf, circuit: ZkSyncCircuit) -> (JobId, ZkSyncCircuit) {
+        let job_id = self.next_job_id;
+        self.next_job_id += 1;
+        println!("started {:?} as job {}", path, job_id);
+        self.in_flight.insert(job_id, path);
+
+        (job_id, circuit)
+    }
+
+    /// Hands the circuit of a failed attempt out again, the service hasn't given up on it
+    fn requeue_job(&mut self, job_id: JobId) {
+        match self.in_flight.remove(&job_id) {
+            Some(path) => self.pending.push_front(path),
+            None => println!("report for unknown job {}", job_id),
+        }
+    }
+
+    /// Moves the circuit file into the subfolder and writes the outcome next to it,
+    /// the outcome keeps the extension of the circuit file so that `circuit.bin`
+    /// and `circuit.json` don't share it
+    fn move_away(&mut self, path: &Path, subdir: &str, extension: &str, outcome: &[u8]) {
+        let target_dir = self.dir.join(subdir);
+        let file_name = path.file_name().expect("circuit file has a name");
+        let outcome_name = format!("{}.{}", file_name.to_string_lossy(), extension);
+        let moved = std::fs::create_dir_all(&target_dir)
+            .and_then(|_| std::fs::write(target_dir.join(outcome_name), outcome))
+            .and_then(|_| std::fs::rename(path, target_dir.join(file_name)));
+        match moved {
+            Ok(()) => {
+                self.seen.remove(path);
+            }
+            Err(e) => println!("failed moving {:?} into {}: {}", path, subdir, e),
+        }
+    }
+
+    fn finish_job(&mut self, job_id: JobId, subdir: &str, extension: &str, outcome: &[u8]) {
+        match self.in_flight.remove(&job_id) {
+            Some(path) => self.move_away(&path, subdir, extension, outcome),
+            None => println!("report for unknown job {}", job_id),
+        }
+    }
+}
+
+/// Decodes a circuit file, decoding panics on malformed files
+fn decode(path: &Path) -> Result<ZkSyncCircuit, String> {
+    catch_unwind(AssertUnwindSafe(|| {
+        decode_circuit_from_file(&path.to_path_buf())
+    }))
+    .map_err(|panic| {
+        panic
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| panic.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown error".to_string())
+    })
+}
+
+/// Serves the circuit files dropped into a directory as jobs. The directory is
+/// polled for new `circuit*.bin` and `circuit*.json` files, finished circuits
+/// are moved into `done/` with the proof next to them, e.g. `circuit_1.bin.proof`,
+/// and the ones the service gives up on into `failed/` with the error. Use the
+/// reporter of the manager to get them moved.
+pub struct DirectoryJobManager {
+    folder: Arc<Mutex<DropFolder>>,
+}
+
+impl DirectoryJobManager {
+    pub fn new<P: AsRef<Path>>(dir: P, poll_interval: Duration) -> Self {
+        let folder = DropFolder {
+            dir: dir.as_ref().to_path_buf(),
+            poll_interval,
+            last_scan: None,
+            next_job_id: 0,
+            seen: HashSet::new(),
+            pending: VecDeque::new(),
+            decoded: VecDeque::new(),
+            in_flight: HashMap::new(),
+            exhausted: HashSet::new(),
+        };
+
+        Self {
+            folder: Arc::new(Mutex::new(folder)),
+        }
+    }
+
+    pub fn reporter(&self) -> DirectoryJobReporter {
+        DirectoryJobReporter {
+            folder: self.folder.clone(),
+        }
+    }
+
+    fn poll_interval(&self) -> Duration {
+        self.folder.lock().unwrap().poll_interval
+    }
+
+    /// Decodes queued circuit files until one of the circuit type turns up,
+    /// malformed files are moved into `failed/` with the decoding error
+    fn take_job(&self, circuit_id: Option<u8>) -> Option<(JobId, ZkSyncCircuit)> {
+        let matches_circuit = |circuit: &ZkSyncCircuit| {
+            circuit_id.map_or(true, |circuit_id| {
+                circuit.numeric_circuit_type() == circuit_id
+            })
+        };
+        let mut folder = self.folder.lock().unwrap();
+        folder.scan();
+        if let Some(idx) = folder
+            .decoded
+            .iter()
+            .position(|(_, circuit)| matches_circuit(circuit))
+        {
+            let (path, circuit) = folder.decoded.remove(idx).unwrap();
+            return Some(folder.start_job(path, circuit));
+        }
+        while let Some(path) = folder.pending.pop_front() {
+            // decoding takes a while, don't block the reporter meanwhile
+            drop(folder);
+            let decoded = decode(&path);
+            folder = self.folder.lock().unwrap();
+            match decoded {
+                Ok(circuit) if matches_circuit(&circuit) => {
+                    return Some(folder.start_job(path, circuit))
+                }
+                Ok(circuit) => folder.decoded.push_back((path, circuit)),
+                Err(reason) => folder.move_away(
+                    &path,
+                    FAILED_DIR,
+                    "error",
+                    format!("decoding failed: {}", reason).as_bytes(),
+                ),
+            }
+        }
+
+        None
+    }
+}
+
+impl JobManager for DirectoryJobManager {
+    fn get_next_job(&mut self) -> (JobId, ZkSyncCircuit) {
+        loop {
+            if let Some(job) = self.try_get_next_job() {
+                return job;
+            }
+            std::thread::sleep(self.poll_interval());
+        }
+    }
+
+    fn get_next_job_by_circuit(&mut self, circuit_id: u8) -> (JobId, ZkSyncCircuit) {
+        loop {
+            if let Some(job) = self.try_get_next_job_by_circuit(circuit_id) {
+                return job;
+            }
+            std::thread::sleep(self.poll_interval());
+        }
+    }
+
+    fn try_get_next_job(&mut self) -> Option<(JobId, ZkSyncCircuit)> {
+        self.take_job(None)
+    }
+
+    fn try_get_next_job_by_circuit(&mut self, circuit_id: u8) -> Option<(JobId, ZkSyncCircuit)> {
+        self.take_job(Some(circuit_id))
+    }
+}
+
+pub struct DirectoryJobReporter {
+    folder: Arc<Mutex<DropFolder>>,
+}
+
+impl JobReporter for DirectoryJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        let mut folder = self.folder.lock().unwrap();
+        match report {
+            JobResult::ProofGenerated(job_id, _, proof, _) => match bincode::serialize(&proof) {
+                Ok(encoding) => folder.finish_job(job_id, DONE_DIR, "proof", &encoding),
+                Err(e) => folder.finish_job(
+                    job_id,
+                    FAILED_DIR,
+                    "error",
+                    format!("failed encoding proof: {}", e).as_bytes(),
+                ),
+            },
+            JobResult::RetriesExhausted(job_id, _) => {
+                folder.exhausted.insert(job_id);
+            }
+            // a failure is final only once the service has given up on the job
+            JobResult::Failure(job_id, reason)
+            | JobResult::FailureWithDebugging(job_id, _, _, reason)
+            | JobResult::AssemblyCorrupted(job_id, reason) => {
+                if folder.exhausted.remove(&job_id) {
+                    folder.finish_job(job_id, FAILED_DIR, "error", reason.as_bytes())
+                } else {
+                    folder.requeue_job(job_id)
+                }
+            }
+            JobResult::Cancelled(job_id) => {
+                folder.finish_job(job_id, FAILED_DIR, "error", b"cancelled")
+            }
+            _ => (),
+        }
+    }
+}
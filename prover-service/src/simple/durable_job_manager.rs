@@ -0,0 +1,339 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use super::*;
+
+const LOG_FILE_NAME: &str = "jobs.log";
+const CIRCUITS_DIR: &str = "circuits";
+const PROOFS_DIR: &str = "proofs";
+
+fn invalid_data<E: std::fmt::Debug>(e: E) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+}
+
+/// Jobs kept in a directory so that they survive a restart. Every state
+/// change is appended to `jobs.log` and synced before it takes effect, the
+/// circuits and the proofs are stored next to it. Jobs that were started
+/// when the service went down are queued again on `open`.
+pub struct DurableJobStore {
+    dir: PathBuf,
+    log: Mutex<File>,
+    // job id, circuit type and state in the order the jobs were added
+    jobs: Mutex<Vec<(JobId, u8, JobState)>>,
+    job_availability: Arc<JobAvailability>,
+}
+
+impl DurableJobStore {
+    pub fn open<P: AsRef<Path>>(dir: P) -> std::io::Result<Arc<Self>> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(dir.join(CIRCUITS_DIR))?;
+        std::fs::create_dir_all(dir.join(PROOFS_DIR))?;
+        let log_path = dir.join(LOG_FILE_NAME);
+
+        let mut jobs: Vec<(JobId, u8, JobState)> = vec![];
+        let mut is_terminated = true;
+        if log_path.exists() {
+            let content = std::fs::read_to_string(&log_path)?;
+            is_terminated = content.is_empty() || content.ends_with('\n');
+            for line in content.lines() {
+                match parse_record(line) {
+                    Some((job_id, circuit_id, state)) => {
+                        match jobs.iter_mut().find(|job| job.0 == job_id) {
+                            Some(job) => job.2 = state,
+                            None => jobs.push((job_id, circuit_id, state)),
+                        }
+                    }
+                    // a crash can leave a partially written last record
+                    None => println!("skipping malformed job record: {}", line),
+                }
+            }
+        }
+        let mut log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+        // new records must not extend the partial one
+        if !is_terminated {
+            writeln!(log)?;
+        }
+        let store = Self {
+            dir,
+            log: Mutex::new(log),
+            jobs: Mutex::new(vec![]),
+            job_availability: Arc::new(JobAvailability::default()),
+        };
+
+        for job in jobs.iter_mut() {
+            if let JobState::Started(job_id) = job.2 {
+                println!("requeueing job {} started before restart", job_id);
+                job.2 = JobState::Created(job_id);
+                store.append(job_id, job.1, &job.2)?;
+            }
+        }
+        *store.jobs.lock().unwrap() = jobs;
+
+        Ok(Arc::new(store))
+    }
+
+    /// Stores the circuit and queues a job for it
+    pub fn add_job(&self, circuit: &ZkSyncCircuit) -> std::io::Result<JobId> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job_id = jobs.iter().map(|job| job.0 + 1).max().unwrap_or(0);
+        let circuit_id = circuit.numeric_circuit_type();
+        let encoding = bincode::serialize(circuit).map_err(invalid_data)?;
+        write_atomically(&self.circuit_path(job_id), &encoding)?;
+        self.append(job_id, circuit_id, &JobState::Created(job_id))?;
+        jobs.push((job_id, circuit_id, JobState::Created(job_id)));
+        drop(jobs);
+        self.job_availability.notify();
+
+        Ok(job_id)
+    }
+
+    /// Marks a queued or started job as cancelled, returns false for unknown
+    /// or finished jobs
+    pub fn cancel(&self, job_id: JobId) -> std::io::Result<bool> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match jobs.iter_mut().find(|job| job.0 == job_id) {
+            Some(job) => job,
+            None => return Ok(false),
+        };
+        if !matches!(
+            job.2,
+            JobState::Created(_) | JobState::Started(_) | JobState::Failure(_, _)
+        ) {
+            return Ok(false);
+        }
+        self.append(job_id, job.1, &JobState::Cancelled(job_id))?;
+        job.2 = JobState::Cancelled(job_id);
+
+        Ok(true)
+    }
+
+    /// Calls the closure with the current state of each job
+    pub fn for_each_job<F: FnMut(JobId, u8, &JobState)>(&self, mut f: F) {
+        for (job_id, circuit_id, state) in self.jobs.lock().unwrap().iter() {
+            f(*job_id, *circuit_id, state)
+        }
+    }
+
+    pub fn proof_path(&self, job_id: JobId) -> PathBuf {
+        self.dir.join(PROOFS_DIR).join(format!("{}.bin", job_id))
+    }
+
+    pub fn read_proof(&self, job_id: JobId) -> std::io::Result<ZkSyncProof<Bn256>> {
+        let encoding = std::fs::read(self.proof_path(job_id))?;
+        bincode::deserialize(&encoding).map_err(invalid_data)
+    }
+
+    fn circuit_path(&self, job_id: JobId) -> PathBuf {
+        self.dir.join(CIRCUITS_DIR).join(format!("{}.bin", job_id))
+    }
+
+    fn read_circuit(&self, job_id: JobId) -> std::io::Result<ZkSyncCircuit> {
+        let encoding = std::fs::read(self.circuit_path(job_id))?;
+        bincode::deserialize(&encoding).map_err(invalid_data)
+    }
+
+    fn append(&self, job_id: JobId, circuit_id: u8, state: &JobState) -> std::io::Result<()> {
+        let mut log = self.log.lock().unwrap();
+        writeln!(log, "{}", format_record(job_id, circuit_id, state))?;
+        log.sync_data()
+    }
+
+    /// Picks a fresh job first and a failed one otherwise, like `SimpleJobManager`
+    fn start_job(&self, circuit_id: Option<u8>) -> Option<(JobId, ZkSyncCircuit)> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let matches_circuit =
+            |job_circuit_id: u8| circuit_id.map_or(true, |circuit_id| circuit_id == job_circuit_id);
+        let job_idx = jobs
+            .iter()
+            .position(|job| matches_circuit(job.1) && matches!(job.2, JobState::Created(_)))
+            .or_else(|| {
+                jobs.iter().position(|job| {
+                    matches_circuit(job.1) && matches!(job.2, JobState::Failure(_, _))
+                })
+            })?;
+        let job = &mut jobs[job_idx];
+        let job_id = job.0;
+        let circuit = match self.read_circuit(job_id) {
+            Ok(circuit) => circuit,
+            Err(e) => {
+                println!("failed reading circuit of job {}: {}", job_id, e);
+                return None;
+            }
+        };
+        if let Err(e) = self.append(job_id, job.1, &JobState::Started(job_id)) {
+            println!("failed recording start of job {}: {}", job_id, e);
+            return None;
+        }
+        job.2 = JobState::Started(job_id);
+
+        Some((job_id, circuit))
+    }
+
+    fn record(&self, job_id: JobId, state: JobState) {
+        let mut jobs = self.jobs.lock().unwrap();
+        let job = match jobs.iter_mut().find(|job| job.0 == job_id) {
+            Some(job) => job,
+            None => {
+                println!("report for unknown job {}", job_id);
+                return;
+            }
+        };
+        // finished jobs stay finished, e.g. when a failure of an abandoned attempt comes in late
+        if matches!(job.2, JobState::Success(_) | JobState::Cancelled(_)) {
+            return;
+        }
+        // an exhausted job stays exhausted when its last failure comes in
+        if matches!(job.2, JobState::Exhausted(_, _)) && matches!(state, JobState::Failure(_, _)) {
+            return;
+        }
+        if let Err(e) = self.append(job_id, job.1, &state) {
+            println!("failed recording state of job {}: {}", job_id, e);
+            return;
+        }
+        let is_pickable = matches!(state, JobState::Created(_) | JobState::Failure(_, _));
+        job.2 = state;
+        drop(jobs);
+        if is_pickable {
+            self.job_availability.notify();
+        }
+    }
+}
+
+fn format_record(job_id: JobId, circuit_id: u8, state: &JobState) -> String {
+    let state = match state {
+        JobState::Created(_) => "created".to_string(),
+        JobState::Started(_) => "started".to_string(),
+        JobState::Success(_) => "succeeded".to_string(),
+        JobState::Cancelled(_) => "cancelled".to_string(),
+        JobState::Exhausted(_, attempts) => format!("exhausted\t{}", attempts),
+        // keep the record on a single line
+        JobState::Failure(_, reason) => format!("failed\t{}", reason.replace(['\t', '\n'], " ")),
+    };
+
+    format!("{}\t{}\t{}", job_id, circuit_id, state)
+}
+
+fn parse_record(line: &str) -> Option<(JobId, u8, JobState)> {
+    let mut fields = line.splitn(4, '\t');
+    let job_id: JobId = fields.next()?.parse().ok()?;
+    let circuit_id: u8 = fields.next()?.parse().ok()?;
+    let state = match (fields.next()?, fields.next()) {
+        ("created", None) => JobState::Created(job_id),
+        ("started", None) => JobState::Started(job_id),
+        ("succeeded", None) => JobState::Success(job_id),
+        ("cancelled", None) => JobState::Cancelled(job_id),
+        ("exhausted", Some(attempts)) => JobState::Exhausted(job_id, attempts.parse().ok()?),
+        ("failed", Some(reason)) => JobState::Failure(job_id, reason.to_string()),
+        _ => return None,
+    };
+
+    Some((job_id, circuit_id, state))
+}
+
+/// Writes into a temporary file first so that a crash never leaves a truncated file behind
+fn write_atomically(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    std::fs::rename(tmp_path, path)
+}
+
+pub struct DurableJobManager {
+    store: Arc<DurableJobStore>,
+}
+
+impl DurableJobManager {
+    pub fn new(store: Arc<DurableJobStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl JobManager for DurableJobManager {
+    fn get_next_job(&mut self) -> (JobId, ZkSyncCircuit) {
+        self.store
+            .job_availability
+            .wait_for(None, JOB_RECHECK_INTERVAL, || self.store.start_job(None))
+            .expect("waiting without timeout yields a job")
+    }
+
+    fn get_next_job_by_circuit(&mut self, circuit_id: u8) -> (JobId, ZkSyncCircuit) {
+        self.store
+            .job_availability
+            .wait_for(None, JOB_RECHECK_INTERVAL, || {
+                self.store.start_job(Some(circuit_id))
+            })
+            .expect("waiting without timeout yields a job")
+    }
+
+    fn try_get_next_job(&mut self) -> Option<(JobId, ZkSyncCircuit)> {
+        self.store.start_job(None)
+    }
+
+    fn try_get_next_job_by_circuit(&mut self, circuit_id: u8) -> Option<(JobId, ZkSyncCircuit)> {
+        self.store.start_job(Some(circuit_id))
+    }
+
+    fn is_cancelled(&mut self, job_id: JobId) -> bool {
+        let jobs = self.store.jobs.lock().unwrap();
+        jobs.iter()
+            .any(|job| job.0 == job_id && matches!(job.2, JobState::Cancelled(_)))
+    }
+
+    fn job_availability(&self) -> Option<Arc<JobAvailability>> {
+        Some(self.store.job_availability.clone())
+    }
+}
+
+/// Records the outcome of the jobs of a `DurableJobStore`, proofs are written
+/// to the store before the job is marked as succeeded.
+pub struct DurableJobReporter {
+    store: Arc<DurableJobStore>,
+}
+
+impl DurableJobReporter {
+    pub fn new(store: Arc<DurableJobStore>) -> Self {
+        Self { store }
+    }
+}
+
+impl JobReporter for DurableJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        let state = match report {
+            JobResult::ProofGenerated(job_id, _, proof, _) => {
+                let stored =
+                    bincode::serialize(&proof)
+                        .map_err(invalid_data)
+                        .and_then(|encoding| {
+                            write_atomically(&self.store.proof_path(job_id), &encoding)
+                        });
+                match stored {
+                    Ok(()) => JobState::Success(job_id),
+                    Err(e) => JobState::Failure(job_id, format!("failed storing proof: {}", e)),
+                }
+            }
+            JobResult::Failure(job_id, reason)
+            | JobResult::FailureWithDebugging(job_id, _, _, reason)
+            | JobResult::AssemblyCorrupted(job_id, reason) => JobState::Failure(job_id, reason),
+            JobResult::Cancelled(job_id) => JobState::Cancelled(job_id),
+            JobResult::RetriesExhausted(job_id, attempts) => JobState::Exhausted(job_id, attempts),
+            _ => return,
+        };
+        let job_id = match state {
+            JobState::Created(job_id)
+            | JobState::Started(job_id)
+            | JobState::Success(job_id)
+            | JobState::Cancelled(job_id)
+            | JobState::Failure(job_id, _)
+            | JobState::Exhausted(job_id, _) => job_id,
+        };
+        self.store.record(job_id, state);
+    }
+}
@@ -0,0 +1,258 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::Mutex,
+};
+
+use super::*;
+use crate::events::JobEvent;
+
+/// Upper bounds of the duration histograms in seconds
+const BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0,
+];
+
+#[derive(Default)]
+struct Histogram {
+    // observations per bucket, the last one is +Inf
+    counts: [u64; BUCKETS.len() + 1],
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(BUCKETS.len());
+        self.counts[bucket] += 1;
+        self.sum += seconds;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(self.counts.iter()) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, bound, cumulative
+            );
+        }
+        cumulative += self.counts[BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, cumulative
+        );
+        let _ = writeln!(out, "{}_sum{} {}", name, braced(labels), self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braced(labels), cumulative);
+    }
+}
+
+fn braced(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels)
+    }
+}
+
+fn circuit_label(circuit_id: Option<u8>) -> String {
+    circuit_id
+        .map(|circuit_id| format!("circuit=\"{}\"", circuit_id))
+        .unwrap_or_default()
+}
+
+#[derive(Default)]
+struct Metrics {
+    // histograms by name and circuit label
+    durations: BTreeMap<(&'static str, String), Histogram>,
+    // counters by name and label
+    counters: BTreeMap<(&'static str, String), u64>,
+    prover_idle: BTreeMap<ProverId, f64>,
+    setup_loader_idle: f64,
+    scheduler_idle: f64,
+}
+
+impl Metrics {
+    fn observe(&mut self, name: &'static str, circuit_id: Option<u8>, duration: Duration) {
+        self.durations
+            .entry((name, circuit_label(circuit_id)))
+            .or_default()
+            .observe(duration);
+    }
+
+    fn count(&mut self, name: &'static str, labels: String) {
+        *self.counters.entry((name, labels)).or_default() += 1;
+    }
+
+    fn record(&mut self, circuit_id: Option<u8>, report: &JobResult) {
+        match report {
+            JobResult::Synthesized(_, duration) => {
+                self.observe("prover_synthesis_seconds", circuit_id, *duration)
+            }
+            JobResult::AssemblyFinalized(_, duration) => {
+                self.observe("prover_assembly_finalize_seconds", circuit_id, *duration)
+            }
+            JobResult::AssemblyDecoded(_, duration) => {
+                self.observe("prover_assembly_decode_seconds", circuit_id, *duration)
+            }
+            JobResult::SetupLoaded(_, duration, cache_hit) => {
+                self.observe("prover_setup_load_seconds", circuit_id, *duration);
+                let name = if *cache_hit {
+                    "prover_setup_cache_hits_total"
+                } else {
+                    "prover_setup_cache_misses_total"
+                };
+                self.count(name, circuit_label(circuit_id));
+            }
+            JobResult::ProofGenerated(_, duration, _, _) => {
+                self.observe("prover_proof_seconds", circuit_id, *duration)
+            }
+            JobResult::Failure(_, _)
+            | JobResult::FailureWithDebugging(_, _, _, _)
+            | JobResult::AssemblyCorrupted(_, _) => {
+                self.count("prover_failures_total", circuit_label(circuit_id))
+            }
+            JobResult::Cancelled(_) => {
+                self.count("prover_cancelled_total", circuit_label(circuit_id))
+            }
+            JobResult::RetryScheduled(_, _, _) => {
+                self.count("prover_retries_total", circuit_label(circuit_id))
+            }
+            JobResult::ProverWaitedIdle(prover_idx, duration) => {
+                self.prover_idle.insert(*prover_idx, duration.as_secs_f64());
+            }
+            JobResult::SetupLoaderWaitedIdle(duration) => {
+                self.setup_loader_idle = duration.as_secs_f64()
+            }
+            JobResult::SchedulerWaitedIdle(duration) => {
+                self.scheduler_idle = duration.as_secs_f64()
+            }
+            _ => (),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut last_name = None;
+        for ((name, labels), histogram) in self.durations.iter() {
+            if last_name != Some(*name) {
+                let _ = writeln!(out, "# TYPE {} histogram", name);
+                last_name = Some(*name);
+            }
+            histogram.render(&mut out, name, labels);
+        }
+        for ((name, labels), value) in self.counters.iter() {
+            if last_name != Some(*name) {
+                let _ = writeln!(out, "# TYPE {} counter", name);
+                last_name = Some(*name);
+            }
+            let _ = writeln!(out, "{}{} {}", name, braced(labels), value);
+        }
+        if !self.prover_idle.is_empty() {
+            let _ = writeln!(out, "# TYPE prover_idle_seconds gauge");
+        }
+        for (prover_idx, seconds) in self.prover_idle.iter() {
+            let _ = writeln!(
+                out,
+                "prover_idle_seconds{{prover=\"{}\"}} {}",
+                prover_idx, seconds
+            );
+        }
+        let _ = writeln!(out, "# TYPE prover_setup_loader_idle_seconds gauge");
+        let _ = writeln!(
+            out,
+            "prover_setup_loader_idle_seconds {}",
+            self.setup_loader_idle
+        );
+        let _ = writeln!(out, "# TYPE prover_scheduler_idle_seconds gauge");
+        let _ = writeln!(out, "prover_scheduler_idle_seconds {}", self.scheduler_idle);
+
+        out
+    }
+}
+
+/// Aggregates the reports into metrics and serves them in the Prometheus
+/// text format. Durations are labeled with the circuit type when the report
+/// comes with the context of its job, idle times are the latest ones.
+#[derive(Clone, Default)]
+pub struct MetricsJobReporter {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+impl MetricsJobReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Current metrics in the Prometheus text format
+    pub fn render(&self) -> String {
+        self.metrics.lock().unwrap().render()
+    }
+
+    /// Answers every request on the address with the current metrics,
+    /// returns the bound address so that port 0 can be used.
+    pub fn serve<A: ToSocketAddrs>(&self, addr: A) -> std::io::Result<SocketAddr> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let reporter = self.clone();
+        std::thread::Builder::new()
+            .name("metrics-exporter".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = reporter.respond(stream) {
+                                println!("failed serving metrics: {}", e);
+                            }
+                        }
+                        Err(e) => println!("failed accepting metrics connection: {}", e),
+                    }
+                }
+            })?;
+        println!("serving metrics on {}", local_addr);
+
+        Ok(local_addr)
+    }
+
+    fn respond(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // the path doesn't matter, read the request head and ignore it
+        let mut request = vec![];
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let read = stream.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..read]);
+        }
+        let body = self.render();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+        stream.flush()
+    }
+}
+
+impl JobReporter for MetricsJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        self.metrics.lock().unwrap().record(None, &report);
+    }
+
+    fn send_event(&mut self, event: JobEvent) {
+        self.metrics
+            .lock()
+            .unwrap()
+            .record(event.circuit_id, &event.result);
+    }
+}
@@ -1,9 +1,15 @@
 use super::*;
 
+pub mod directory_job_manager;
+pub mod durable_job_manager;
+pub mod metrics_job_reporter;
 pub mod simple_artifact_manager;
 // pub mod http_artifact_manager;
 pub mod simple_job_manager;
 
+pub use directory_job_manager::*;
+pub use durable_job_manager::*;
+pub use metrics_job_reporter::*;
 pub use simple_artifact_manager::*;
 // pub use http_artifact_manager::*;
 pub use simple_job_manager::*;
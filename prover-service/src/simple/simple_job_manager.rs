@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     io::Write,
     sync::{
         atomic::{AtomicUsize, Ordering},
@@ -17,10 +18,15 @@ pub enum JobState {
     Started(JobId),
     Failure(JobId, String),
     Success(JobId),
+    Cancelled(JobId),
+    /// Failed after the retry policy of the service ran out, never picked again
+    Exhausted(JobId, usize),
 }
 
 pub struct SimpleJobManager {
     jobs: Arc<Mutex<Vec<(usize, ZkSyncCircuit, JobState)>>>,
+    priorities: HashMap<u8, JobPriority>,
+    job_availability: Arc<JobAvailability>,
 }
 
 impl SimpleJobManager {
@@ -36,7 +42,17 @@ impl SimpleJobManager {
         }
         append_into_file("job_map", &job_map.join("\n"));
         drop(guarded_jobs);
-        Self { jobs }
+        Self {
+            jobs,
+            priorities: HashMap::new(),
+            job_availability: Arc::new(JobAvailability::default()),
+        }
+    }
+
+    /// Priorities per circuit type, circuits without one get the lowest priority
+    pub fn with_priorities(mut self, priorities: HashMap<u8, JobPriority>) -> Self {
+        self.priorities = priorities;
+        self
     }
 
     fn get_job(&mut self, circuit_id: Option<u8>) -> Option<(JobId, ZkSyncCircuit)> {
@@ -83,19 +99,24 @@ impl SimpleJobManager {
 
 impl JobManager for SimpleJobManager {
     fn get_next_job(&mut self) -> (JobId, ZkSyncCircuit) {
-        loop {
-            if let Some(job) = self.get_job(None) {
-                return job;
-            }
-        }
+        let job_availability = self.job_availability.clone();
+        job_availability
+            .wait_for(None, JOB_RECHECK_INTERVAL, || self.get_job(None))
+            .expect("waiting without timeout yields a job")
     }
 
     fn get_next_job_by_circuit(&mut self, circuit_id: u8) -> (JobId, ZkSyncCircuit) {
-        loop {
-            if let Some(job) = self.get_job(Some(circuit_id)) {
-                return job;
-            }
-        }
+        let job_availability = self.job_availability.clone();
+        job_availability
+            .wait_for(None, JOB_RECHECK_INTERVAL, || {
+                self.get_job(Some(circuit_id))
+            })
+            .expect("waiting without timeout yields a job")
+    }
+
+    fn get_next_job_timeout(&mut self, timeout: Duration) -> Option<(JobId, ZkSyncCircuit)> {
+        let job_availability = self.job_availability.clone();
+        job_availability.wait_for(Some(timeout), JOB_RECHECK_INTERVAL, || self.get_job(None))
     }
 
     fn try_get_next_job(&mut self) -> Option<(JobId, ZkSyncCircuit)> {
@@ -105,11 +126,62 @@ impl JobManager for SimpleJobManager {
     fn try_get_next_job_by_circuit(&mut self, circuit_id: u8) -> Option<(JobId, ZkSyncCircuit)> {
         self.get_job(Some(circuit_id))
     }
+
+    fn is_cancelled(&mut self, job_id: JobId) -> bool {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .any(|job| job.0 == job_id && matches!(job.2, JobState::Cancelled(_)))
+    }
+
+    fn job_priority(&mut self, _job_id: JobId, circuit_id: u8) -> JobPriority {
+        self.priorities
+            .get(&circuit_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn job_availability(&self) -> Option<Arc<JobAvailability>> {
+        Some(self.job_availability.clone())
+    }
+}
+
+/// Share of setup loads served by already loaded setups
+#[derive(Debug, Default)]
+pub struct SetupHitRate {
+    hits: AtomicUsize,
+    loads: AtomicUsize,
+}
+
+impl SetupHitRate {
+    fn record(&self, cache_hit: bool) {
+        if cache_hit {
+            self.hits.fetch_add(1, Ordering::SeqCst);
+        }
+        self.loads.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn loads(&self) -> usize {
+        self.loads.load(Ordering::SeqCst)
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::SeqCst)
+    }
+
+    pub fn hit_rate(&self) -> f64 {
+        let loads = self.loads();
+        if loads == 0 {
+            return 0.0;
+        }
+        self.hits.load(Ordering::SeqCst) as f64 / loads as f64
+    }
 }
 
 pub struct SimpleJobReporter {
     jobs: Arc<Mutex<Vec<(usize, ZkSyncCircuit, JobState)>>>,
     next_job_id: AtomicUsize,
+    setup_hit_rate: Arc<SetupHitRate>,
+    job_availability: Option<Arc<JobAvailability>>,
 }
 
 impl SimpleJobReporter {
@@ -117,8 +189,21 @@ impl SimpleJobReporter {
         Self {
             jobs,
             next_job_id: AtomicUsize::new(0),
+            setup_hit_rate: Arc::new(SetupHitRate::default()),
+            job_availability: None,
         }
     }
+
+    /// Wakes up the waiting callers of the job manager when a job can be picked again
+    pub fn with_job_availability(mut self, job_availability: Arc<JobAvailability>) -> Self {
+        self.job_availability = Some(job_availability);
+        self
+    }
+
+    /// Stays up to date after the reporter is handed to the prover service
+    pub fn setup_hit_rate(&self) -> Arc<SetupHitRate> {
+        self.setup_hit_rate.clone()
+    }
 }
 
 impl JobReporter for SimpleJobReporter {
@@ -137,6 +222,13 @@ impl JobReporter for SimpleJobReporter {
                 println!("job scheduler waited {:?}", duration);
                 return;
             }
+            JobResult::ServiceReady(duration) => {
+                println!("prover service warmed up in {:?}", duration);
+                return;
+            }
+            JobResult::SetupLoaded(_, _, cache_hit) => {
+                self.setup_hit_rate.record(*cache_hit);
+            }
             _ => (),
         }
 
@@ -149,11 +241,16 @@ impl JobReporter for SimpleJobReporter {
             JobResult::AssemblyEncoded(job_id, _) => job_id,
             JobResult::AssemblyDecoded(job_id, _) => job_id,
             JobResult::AssemblyTransferred(job_id, _) => job_id,
+            JobResult::AssemblyCorrupted(job_id, _) => job_id,
+            JobResult::Cancelled(job_id) => job_id,
+            JobResult::RetryScheduled(job_id, _, _) => job_id,
+            JobResult::RetriesExhausted(job_id, _) => job_id,
             JobResult::FailureWithDebugging(job_id, _, _, _) => job_id,
             _ => unreachable!(),
         };
 
         let mut this_job = None;
+        let mut is_pickable = false;
 
         let mut jobs = self.jobs.lock().unwrap();
         rand::thread_rng().shuffle(&mut jobs);
@@ -180,16 +277,31 @@ impl JobReporter for SimpleJobReporter {
                     job.0 = new_job_id;
                     job.2 = JobState::Created(new_job_id);
                 }
-                JobResult::Failure(_, msg) => {
+                JobResult::RetriesExhausted(_, attempts) => {
+                    job.2 = JobState::Exhausted(job_id, *attempts);
+                }
+                // the failure that follows exhausted retries doesn't make the job pickable again
+                _ if matches!(job.2, JobState::Exhausted(..)) => (),
+                JobResult::Failure(_, msg) | JobResult::AssemblyCorrupted(_, msg) => {
                     job.2 = JobState::Failure(job_id, msg.clone());
                 }
                 JobResult::FailureWithDebugging(_, _, _, msg) => {
                     job.2 = JobState::Failure(job_id, msg.clone());
                 }
+                JobResult::Cancelled(_) => {
+                    job.2 = JobState::Cancelled(job_id);
+                }
                 _ => (),
             }
+            is_pickable = matches!(job.2, JobState::Created(_) | JobState::Failure(_, _));
         }
         rand::thread_rng().shuffle(&mut jobs);
+        drop(jobs);
+        if let Some(job_availability) = self.job_availability.as_ref() {
+            if is_pickable {
+                job_availability.notify();
+            }
+        }
         handle_report(&report, job_id);
     }
 }
@@ -239,6 +351,24 @@ fn handle_report(report: &JobResult, job_id: usize) {
         JobResult::Failure(_, ref duration) => {
             append_into_file("failure.log", &format!("{}\t{:?}", job_id, duration));
         }
+        JobResult::AssemblyCorrupted(_, ref msg) => {
+            append_into_file("assembly_corrupted.log", &format!("{}\t{}", job_id, msg));
+        }
+        JobResult::Cancelled(_) => {
+            append_into_file("cancelled.log", &format!("{}", job_id));
+        }
+        JobResult::RetryScheduled(_, attempt, ref msg) => {
+            append_into_file(
+                "retry_scheduled.log",
+                &format!("{}\t{}\t{}", job_id, attempt, msg),
+            );
+        }
+        JobResult::RetriesExhausted(_, attempts) => {
+            append_into_file(
+                "retries_exhausted.log",
+                &format!("{}\t{}", job_id, attempts),
+            );
+        }
         JobResult::FailureWithDebugging(job_id, circuit_id, ref assembly_encoding, ref msg) => {
             let artifacts_dir = get_artifacts_dir();
             let artifacts_dir = artifacts_dir.to_string_lossy().to_string();
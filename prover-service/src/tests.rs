@@ -26,19 +26,33 @@ use zkevm_test_harness::{
 };
 
 use crate::{
+    events::{JobContexts, JobEvent, ReportSender},
     remote_synth::{
         calculate_serialization_capacity_for_proving_assembly, custom_assembly_deserialization,
-        custom_assembly_serialization, deserialize_job, run_remote_synthesizer, serialize_job,
-        EncodedArtifactSender,
+        custom_assembly_serialization, deliver_encoding, deserialize_job, run_remote_synthesizer,
+        serialize_job, serialize_job_with_options, ColumnEncoding, Compression, DecodeError,
+        EncodedArtifactSender, EncodingHeader, EncodingOptions, Section, SynthesizerContext,
+        BN256_FIELD_ID, ENCODING_FORMAT_VERSION,
+    },
+    reporters::{BufferedJobReporter, FilterJobReporter, MapJobReporter, MultiJobReporter},
+    routing::{
+        ArtifactRouter, ConsistentHashRouter, LeastLoadedRouter, RoundRobinRouter, StaticRouter,
     },
     run_prover::{
         create_prover_instances, run_prover_with_local_synthesizer,
         run_prover_with_remote_synthesizer,
     },
+    scheduling::PriorityQueue,
+    setup_cache::{LfuPolicy, LruPolicy, PinnedPolicy, SetupCache, SetupCachePolicy, TakenSlot},
     simple::{
+        directory_job_manager::DirectoryJobManager,
+        durable_job_manager::{DurableJobManager, DurableJobReporter, DurableJobStore},
+        metrics_job_reporter::MetricsJobReporter,
         simple_artifact_manager::{SimpleArtifactManager, SETUP_FILE_NAME},
         simple_job_manager::{JobState, SimpleJobManager, SimpleJobReporter},
     },
+    transport::tcp::{TcpArtifactSender, TcpRemoteSynthesizer, TcpTransportConfig},
+    worker_pool::{WorkerPool, WorkerPoolConfig, WorkerPoolKind, WorkerPoolStats},
 };
 
 use super::utils::*;
@@ -101,37 +115,1089 @@ impl SimpleRemoteSynthesizer {
     }
 }
 
-#[test]
-fn test_prover_service_with_shuffled_circuits() {
+type Jobs = Arc<Mutex<Vec<(usize, ZkSyncCircuit, JobState)>>>;
+
+/// A fresh job for every circuit of the artifacts directory
+fn jobs_from_artifacts() -> Jobs {
     assert!(std::env::var("CRS_FILE").is_ok());
     let artifacts_dir = get_artifacts_dir();
 
     let circuits = read_circuits_from_directory(&artifacts_dir);
     assert!(!circuits.is_empty());
-    let mut selected_circuits = vec![];
-    for circuit in circuits {
-        selected_circuits.push(circuit.clone());
-    }
-
-    rand::thread_rng().shuffle(&mut selected_circuits);
 
-    let jobs: Vec<(usize, ZkSyncCircuit, JobState)> = selected_circuits
+    let jobs: Vec<(usize, ZkSyncCircuit, JobState)> = circuits
         .into_iter()
         .enumerate()
         .map(|(idx, c)| (idx, c, JobState::Created(idx)))
         .collect();
-    let jobs = Arc::new(Mutex::new(jobs));
+
+    Arc::new(Mutex::new(jobs))
+}
+
+/// Polls the condition until it holds, returns false once the timeout passes
+fn wait_until<F: FnMut() -> bool>(timeout: Duration, mut condition: F) -> bool {
+    let started = std::time::Instant::now();
+    while !condition() {
+        if started.elapsed() > timeout {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    true
+}
+
+/// Runs the service over the jobs until the reports satisfy the condition and shuts
+/// it down, every report is returned in the order it arrived. The job states are
+/// kept up to date like with a `SimpleJobReporter`.
+fn run_service_until<AM, P, F>(
+    artifact_manager: AM,
+    jobs: Jobs,
+    params: P,
+    mut condition: F,
+    shutdown_timeout: Duration,
+) -> Vec<JobResult>
+where
+    AM: ArtifactProvider + 'static,
+    P: Params + 'static,
+    F: FnMut(&[JobResult]) -> bool,
+{
+    let job_manager = SimpleJobManager::new(jobs.clone());
+    let reports = Arc::new(Mutex::new(vec![]));
+    let job_reporter = MultiJobReporter::new()
+        .with(SimpleJobReporter::new(jobs))
+        .with(CollectingJobReporter(reports.clone()));
+
+    let shutdown_handle = run_prover_with_local_synthesizer(
+        artifact_manager,
+        job_manager,
+        job_reporter,
+        None,
+        params,
+    );
+    assert!(wait_until(Duration::from_secs(600), || condition(
+        &reports.lock().unwrap()
+    )));
+    assert!(shutdown_handle.shutdown(shutdown_timeout));
+
+    let reports = std::mem::take(&mut *reports.lock().unwrap());
+    reports
+}
+
+struct TestingParamsWithoutSetupAffinity;
+
+impl Params for TestingParamsWithoutSetupAffinity {
+    fn number_of_parallel_synthesis(&self) -> u8 {
+        3
+    }
+
+    fn number_of_setup_slots(&self) -> u8 {
+        4
+    }
+
+    fn polling_duration(&self) -> Duration {
+        Duration::from_millis(1)
+    }
+
+    fn setup_affinity_window(&self) -> usize {
+        0
+    }
+}
+
+/// Proves every circuit once in the given order and returns the setup cache hits
+fn setup_hits_of_run<P: Params + 'static>(circuits: &[ZkSyncCircuit], params: P) -> usize {
+    let jobs: Jobs = Arc::new(Mutex::new(
+        circuits
+            .iter()
+            .cloned()
+            .enumerate()
+            .map(|(idx, circuit)| (idx, circuit, JobState::Created(idx)))
+            .collect(),
+    ));
+    let setup_loads = |reports: &[JobResult]| -> Vec<bool> {
+        reports
+            .iter()
+            .filter_map(|report| match report {
+                JobResult::SetupLoaded(_, _, cache_hit) => Some(*cache_hit),
+                _ => None,
+            })
+            .collect()
+    };
+    let reports = run_service_until(
+        SimpleArtifactManager,
+        jobs,
+        params,
+        |reports| setup_loads(reports).len() >= circuits.len(),
+        Duration::from_secs(60),
+    );
+
+    // jobs proven again after the first round don't count
+    setup_loads(&reports)
+        .into_iter()
+        .take(circuits.len())
+        .filter(|cache_hit| *cache_hit)
+        .count()
+}
+
+#[test]
+fn test_prover_service_with_shuffled_circuits() {
+    let mut circuits: Vec<_> = jobs_from_artifacts()
+        .lock()
+        .unwrap()
+        .drain(..)
+        .map(|(_, circuit, _)| circuit)
+        .collect();
+    rand::thread_rng().shuffle(&mut circuits);
+
+    // setup affinity of the scheduler batches the shuffled circuits by setup
+    let hits_with_affinity = setup_hits_of_run(&circuits, TestingParams);
+    let hits_without_affinity = setup_hits_of_run(&circuits, TestingParamsWithoutSetupAffinity);
+    assert!(hits_with_affinity >= hits_without_affinity);
+}
+
+#[test]
+fn test_prover_service_setup_hit_rate_of_single_circuit_type() {
+    let jobs = jobs_from_artifacts();
+    {
+        // the first setup load is the only miss
+        let mut jobs = jobs.lock().unwrap();
+        let circuit = jobs[0].1.clone();
+        *jobs = (0..3)
+            .map(|idx| (idx, circuit.clone(), JobState::Created(idx)))
+            .collect();
+    }
     let job_manager = SimpleJobManager::new(jobs.clone());
     let job_reporter = SimpleJobReporter::new(jobs);
+    let setup_hit_rate = job_reporter.setup_hit_rate();
     let artifact_manager = SimpleArtifactManager;
 
-    run_prover_with_local_synthesizer(
+    let shutdown_handle = run_prover_with_local_synthesizer(
         artifact_manager,
         job_manager,
         job_reporter,
         None,
         TestingParams,
     );
+    assert!(wait_until(Duration::from_secs(600), || setup_hit_rate
+        .loads()
+        >= 3));
+    assert!(shutdown_handle.shutdown(Duration::from_secs(60)));
+
+    let loads = setup_hit_rate.loads();
+    assert_eq!(setup_hit_rate.hits(), loads - 1);
+    assert_eq!(setup_hit_rate.hit_rate(), (loads - 1) as f64 / loads as f64);
+}
+
+#[test]
+fn test_prover_service_shutdown_drains_in_flight_jobs() {
+    let jobs = jobs_from_artifacts();
+    // shut down while some jobs are in flight
+    run_service_until(
+        SimpleArtifactManager,
+        jobs.clone(),
+        TestingParams,
+        |_| {
+            jobs.lock()
+                .unwrap()
+                .iter()
+                .any(|(_, _, state)| matches!(state, JobState::Started(_)))
+        },
+        Duration::from_secs(600),
+    );
+
+    // every picked job has been finished and reported
+    for (job_id, _, state) in jobs.lock().unwrap().iter() {
+        assert!(
+            !matches!(state, JobState::Started(_)),
+            "job {} is still in flight",
+            job_id
+        );
+    }
+}
+
+#[test]
+fn test_prover_service_shutdown_drains_after_synthesis_panic() {
+    let jobs = jobs_from_artifacts();
+    {
+        // synthesizing a circuit without its witness panics
+        let mut jobs = jobs.lock().unwrap();
+        jobs.truncate(1);
+        jobs[0].1.erase_witness();
+    }
+    // the assembly of the panicked job is back in the pool, so the service drains
+    run_service_until(
+        SimpleArtifactManager,
+        jobs,
+        TestingParams,
+        |reports| {
+            reports.iter().any(|report| {
+                matches!(
+                    report,
+                    JobResult::Failure(0, msg) if msg == "synthesize thread panicked"
+                )
+            })
+        },
+        Duration::from_secs(60),
+    );
+}
+
+#[test]
+fn test_prover_service_drops_cancelled_jobs() {
+    let jobs = jobs_from_artifacts();
+    // cancel every job right after it has been picked, cancelled jobs return
+    // their assemblies so there is nothing left to drain
+    run_service_until(
+        SimpleArtifactManager,
+        jobs.clone(),
+        TestingParams,
+        |_| {
+            let mut jobs = jobs.lock().unwrap();
+            for job in jobs.iter_mut() {
+                if let JobState::Started(job_id) = job.2 {
+                    job.2 = JobState::Cancelled(job_id);
+                }
+            }
+            jobs.iter()
+                .all(|(_, _, state)| matches!(state, JobState::Cancelled(_)))
+        },
+        Duration::from_secs(10),
+    );
+
+    for (job_id, _, state) in jobs.lock().unwrap().iter() {
+        assert!(
+            matches!(state, JobState::Cancelled(_)),
+            "job {} is not cancelled: {:?}",
+            job_id,
+            state
+        );
+    }
+}
+
+struct TestingParamsWithStageTimeouts;
+
+impl Params for TestingParamsWithStageTimeouts {
+    fn number_of_parallel_synthesis(&self) -> u8 {
+        3
+    }
+
+    fn number_of_setup_slots(&self) -> u8 {
+        4
+    }
+
+    fn stage_timeout(&self, stage: JobStage) -> Option<Duration> {
+        match stage {
+            // the larger circuits synthesize for longer
+            JobStage::Synthesis => Some(Duration::from_secs(1)),
+            _ => None,
+        }
+    }
+}
+
+struct CollectingJobReporter(Arc<Mutex<Vec<JobResult>>>);
+
+impl JobReporter for CollectingJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        self.0.lock().unwrap().push(report);
+    }
+}
+
+#[test]
+fn test_prover_service_fails_jobs_exceeding_stage_timeout() {
+    // timed out jobs get fresh assemblies and free them once they return,
+    // so the service still drains
+    run_service_until(
+        SimpleArtifactManager,
+        jobs_from_artifacts(),
+        TestingParamsWithStageTimeouts,
+        |reports| {
+            reports.iter().any(|report| {
+                matches!(
+                    report,
+                    JobResult::Failure(_, msg) if msg.contains("Synthesis stage timed out")
+                )
+            })
+        },
+        Duration::from_secs(120),
+    );
+}
+
+#[test]
+fn test_priority_queue_serves_highest_priority_first() {
+    let mut queue = PriorityQueue::new(Duration::from_secs(60));
+    queue.push("low", 1);
+    queue.push("high", 5);
+    queue.push("second high", 5);
+    queue.push("lowest", 0);
+
+    assert_eq!(queue.pop(), Some("high"));
+    assert_eq!(queue.pop(), Some("second high"));
+    assert_eq!(queue.pop(), Some("low"));
+    assert_eq!(queue.pop(), Some("lowest"));
+    assert_eq!(queue.pop(), None);
+}
+
+#[test]
+fn test_priority_queue_ages_waiting_entries() {
+    let mut queue = PriorityQueue::new(Duration::from_millis(10));
+    queue.push("old", 0);
+    std::thread::sleep(Duration::from_millis(100));
+    queue.push("new", 3);
+
+    // the old entry has gained more levels than the new one is ahead
+    assert_eq!(queue.pop(), Some("old"));
+    assert_eq!(queue.pop(), Some("new"));
+    assert!(queue.is_empty());
+}
+
+#[test]
+fn test_priority_queue_prefers_within_skip_window() {
+    let mut queue = PriorityQueue::new(Duration::from_secs(60));
+    for (idx, circuit_id) in [1u8, 2, 1, 2, 2].into_iter().enumerate() {
+        queue.push((idx, circuit_id), 0);
+    }
+    let is_resident = |(_, circuit_id): &(usize, u8)| *circuit_id == 2;
+
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((1, 2)));
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((3, 2)));
+    // the oldest job was passed over twice already
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((0, 1)));
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((4, 2)));
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((2, 1)));
+    assert_eq!(queue.pop_preferring(is_resident, 2), None);
+
+    // higher priorities are never passed over
+    queue.push((5, 2), 0);
+    queue.push((6, 1), 1);
+    assert_eq!(queue.pop_preferring(is_resident, 2), Some((6, 1)));
+}
+
+#[test]
+fn test_setup_cache_policies() {
+    let mut lru = LruPolicy::default();
+    for circuit_id in [1, 2, 3, 1] {
+        lru.on_access(circuit_id);
+    }
+    assert_eq!(lru.select_victim(&[1, 2, 3]), Some(2));
+    assert_eq!(lru.select_victim(&[1, 3]), Some(3));
+    assert_eq!(lru.select_victim(&[]), None);
+
+    let mut lfu = LfuPolicy::default();
+    for circuit_id in [1, 1, 2, 3, 3, 2, 1] {
+        lfu.on_access(circuit_id);
+    }
+    // 2 and 3 are used twice, 2 more recently
+    assert_eq!(lfu.select_victim(&[1, 2, 3]), Some(3));
+    // a reloaded setup starts counting from scratch
+    lfu.on_evict(1);
+    lfu.on_load(1);
+    assert_eq!(lfu.select_victim(&[1, 2, 3]), Some(1));
+
+    let mut pinned = PinnedPolicy::new(
+        [0u8, 1].into_iter().collect(),
+        Box::new(LruPolicy::default()),
+    );
+    for circuit_id in [2, 0, 1, 3] {
+        pinned.on_access(circuit_id);
+    }
+    assert_eq!(pinned.select_victim(&[0, 1, 2, 3]), Some(2));
+    assert_eq!(pinned.select_victim(&[0, 1]), None);
+}
+
+#[test]
+fn test_setup_slot_is_returned_when_loading_panics() {
+    let setup_cache = Mutex::new(SetupCache::new(1, Box::new(LruPolicy::default())));
+    let mut cache = setup_cache.lock().unwrap();
+    let slot_idx = cache.reserve_slot().unwrap();
+    let setup = cache.take_slot(slot_idx, 3);
+    drop(cache);
+    let loading = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _setup = TakenSlot::new(&setup_cache, slot_idx, setup);
+        panic!("setup encoding is truncated");
+    }));
+    assert!(loading.is_err());
+
+    let mut cache = setup_cache.lock().unwrap();
+    assert!(!cache.is_loading(3));
+    assert!(cache.get(3).is_none());
+    assert_eq!(cache.reserve_slot(), Some(slot_idx));
+}
+
+struct TestingParamsWithPreload(Vec<u8>);
+
+impl Params for TestingParamsWithPreload {
+    fn number_of_parallel_synthesis(&self) -> u8 {
+        3
+    }
+
+    fn number_of_setup_slots(&self) -> u8 {
+        4
+    }
+
+    fn preload_circuits(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+}
+
+#[test]
+fn test_prover_service_preloads_setups_before_scheduling() {
+    let jobs = jobs_from_artifacts();
+    let preloaded_circuit_id = jobs.lock().unwrap()[0].1.numeric_circuit_type();
+    let preloaded_jobs: Vec<JobId> = jobs
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, c, _)| c.numeric_circuit_type() == preloaded_circuit_id)
+        .map(|(job_id, _, _)| *job_id)
+        .collect();
+    let setup_loads = |reports: &[JobResult]| -> Vec<(JobId, bool)> {
+        reports
+            .iter()
+            .filter_map(|report| match report {
+                JobResult::SetupLoaded(job_id, _, cache_hit) => Some((*job_id, *cache_hit)),
+                _ => None,
+            })
+            .collect()
+    };
+    let reports = run_service_until(
+        SimpleArtifactManager,
+        jobs,
+        TestingParamsWithPreload(vec![preloaded_circuit_id]),
+        |reports| {
+            setup_loads(reports)
+                .iter()
+                .any(|(job_id, _)| preloaded_jobs.contains(job_id))
+        },
+        Duration::from_secs(60),
+    );
+
+    let ready_idx = reports
+        .iter()
+        .position(|report| matches!(report, JobResult::ServiceReady(_)))
+        .expect("service never became ready");
+    assert_eq!(
+        reports
+            .iter()
+            .filter(|report| matches!(report, JobResult::ServiceReady(_)))
+            .count(),
+        1
+    );
+    assert!(!reports[..ready_idx]
+        .iter()
+        .any(|report| matches!(report, JobResult::SchedulerWaitedIdle(_))));
+    // without preloading the first load of the circuit type would miss the cache,
+    // later ones may follow an eviction
+    let first_preloaded_load = setup_loads(&reports)
+        .into_iter()
+        .find(|(job_id, _)| preloaded_jobs.contains(job_id));
+    assert!(matches!(first_preloaded_load, Some((_, true))));
+}
+
+#[test]
+fn test_retry_policy() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        backoff: Duration::from_millis(100),
+        retryable: vec![FailureClass::Proving],
+    };
+    assert!(policy.should_retry(FailureClass::Proving, 1));
+    assert!(policy.should_retry(FailureClass::Proving, 2));
+    assert!(!policy.should_retry(FailureClass::Proving, 3));
+    assert!(!policy.should_retry(FailureClass::MissingArtifact, 1));
+
+    assert_eq!(policy.backoff(1), Duration::from_millis(100));
+    assert_eq!(policy.backoff(2), Duration::from_millis(200));
+    assert_eq!(policy.backoff(3), Duration::from_millis(400));
+}
+
+#[test]
+fn test_report_sender_stamps_job_context() {
+    let (sender, receiver) = channel();
+    let contexts = Arc::new(JobContexts::default());
+    contexts.set_prover_device_ids(1, vec![2, 3]);
+    let report_sender = ReportSender::new(sender, contexts.clone());
+
+    contexts.start(7, 4);
+    contexts.enter_stage(7, JobStage::Proving, Some(1));
+    contexts.set_attempt(7, 2);
+    report_sender
+        .send(JobResult::Failure(7, "failed".to_string()))
+        .unwrap();
+    report_sender
+        .send_with_job(7, JobResult::ProverWaitedIdle(1, Duration::ZERO))
+        .unwrap();
+    contexts.finish(7);
+    report_sender.send(JobResult::Cancelled(7)).unwrap();
+    report_sender
+        .send(JobResult::SchedulerWaitedIdle(Duration::ZERO))
+        .unwrap();
+
+    let events: Vec<JobEvent> = receiver.try_iter().collect();
+    assert_eq!(events.len(), 4);
+    assert!(events
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    for event in &events[..2] {
+        assert_eq!(event.job_id, Some(7));
+        assert_eq!(event.circuit_id, Some(4));
+        assert_eq!(event.stage, Some(JobStage::Proving));
+        assert_eq!(event.prover_idx, Some(1));
+        assert_eq!(event.device_ids, vec![2, 3]);
+        assert_eq!(event.attempt, 2);
+    }
+    // a finished job keeps its id but loses its context
+    assert_eq!(events[2].job_id, Some(7));
+    assert_eq!(events[2].circuit_id, None);
+    assert_eq!(events[2].attempt, 0);
+    assert_eq!(events[3].job_id, None);
+    assert!(events[3].device_ids.is_empty());
+}
+
+#[test]
+fn test_metrics_job_reporter() {
+    let mut reporter = MetricsJobReporter::new();
+    let event = |circuit_id: Option<u8>, result: JobResult| JobEvent {
+        timestamp: Duration::ZERO,
+        job_id: result.job_id(),
+        circuit_id,
+        stage: None,
+        prover_idx: None,
+        device_ids: vec![],
+        attempt: 1,
+        result,
+    };
+    reporter.send_event(event(
+        Some(3),
+        JobResult::Synthesized(1, Duration::from_millis(200)),
+    ));
+    reporter.send_event(event(
+        Some(3),
+        JobResult::Synthesized(2, Duration::from_secs(20)),
+    ));
+    reporter.send_event(event(
+        Some(3),
+        JobResult::SetupLoaded(1, Duration::from_secs(2), true),
+    ));
+    reporter.send_event(event(Some(5), JobResult::Failure(3, "failed".to_string())));
+    reporter.send_report(JobResult::ProverWaitedIdle(0, Duration::from_millis(1500)));
+
+    let addr = reporter.serve("127.0.0.1:0").unwrap();
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    println!("{}", response);
+
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    for line in [
+        "# TYPE prover_synthesis_seconds histogram",
+        "prover_synthesis_seconds_bucket{circuit=\"3\",le=\"0.5\"} 1",
+        "prover_synthesis_seconds_bucket{circuit=\"3\",le=\"30\"} 2",
+        "prover_synthesis_seconds_bucket{circuit=\"3\",le=\"+Inf\"} 2",
+        "prover_synthesis_seconds_count{circuit=\"3\"} 2",
+        "prover_setup_load_seconds_count{circuit=\"3\"} 1",
+        "prover_setup_cache_hits_total{circuit=\"3\"} 1",
+        "prover_failures_total{circuit=\"5\"} 1",
+        "prover_idle_seconds{prover=\"0\"} 1.5",
+    ] {
+        assert!(response.contains(line), "missing {}", line);
+    }
+}
+
+#[test]
+fn test_composed_job_reporters() {
+    let all = Arc::new(Mutex::new(vec![]));
+    let failures = Arc::new(Mutex::new(vec![]));
+    let mapped = Arc::new(Mutex::new(vec![]));
+    let mut reporter = MultiJobReporter::new()
+        .with(CollectingJobReporter(all.clone()))
+        .with(FilterJobReporter::failures(CollectingJobReporter(
+            failures.clone(),
+        )))
+        .with(MapJobReporter::new(
+            CollectingJobReporter(mapped.clone()),
+            |report| match report {
+                JobResult::Cancelled(job_id) => {
+                    Some(JobResult::Failure(job_id, "cancelled".to_string()))
+                }
+                JobResult::SchedulerWaitedIdle(_) => None,
+                report => Some(report),
+            },
+        ));
+
+    reporter.send_report(JobResult::Synthesized(1, Duration::ZERO));
+    reporter.send_report(JobResult::Failure(2, "failed".to_string()));
+    reporter.send_report(JobResult::Cancelled(3));
+    reporter.send_report(JobResult::SchedulerWaitedIdle(Duration::ZERO));
+
+    assert_eq!(all.lock().unwrap().len(), 4);
+    let failures = failures.lock().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert!(matches!(failures[0], JobResult::Failure(2, _)));
+    let mapped = mapped.lock().unwrap();
+    assert_eq!(mapped.len(), 3);
+    assert!(matches!(mapped[2], JobResult::Failure(3, _)));
+}
+
+// holds every report until the gate is dropped
+struct GatedJobReporter(Arc<Mutex<Vec<JobResult>>>, Receiver<()>);
+
+impl JobReporter for GatedJobReporter {
+    fn send_report(&mut self, report: JobResult) {
+        let _ = self.1.recv_timeout(Duration::from_secs(60));
+        self.0.lock().unwrap().push(report);
+    }
+}
+
+#[test]
+fn test_buffered_job_reporter_does_not_block() {
+    let reports = Arc::new(Mutex::new(vec![]));
+    let (gate, gate_receiver) = channel::<()>();
+    let mut reporter =
+        BufferedJobReporter::new(GatedJobReporter(reports.clone(), gate_receiver), 4);
+
+    for job_id in 0..16 {
+        reporter.send_report(JobResult::Cancelled(job_id));
+    }
+    // every send returned while the wrapped reporter is still held by the gate
+    assert!(reports.lock().unwrap().is_empty());
+    let dropped = reporter.dropped();
+    assert!(dropped > 0);
+
+    // dropping flushes whatever is buffered
+    drop(gate);
+    drop(reporter);
+    assert_eq!(reports.lock().unwrap().len() + dropped, 16);
+}
+
+// serves setups but no vks so that every proof fails after it is generated
+struct ArtifactManagerWithoutVks;
+
+impl ArtifactProvider for ArtifactManagerWithoutVks {
+    type ArtifactError = std::io::Error;
+
+    fn get_setup(&self, circuit_id: u8) -> Result<Box<dyn Read>, Self::ArtifactError> {
+        SimpleArtifactManager.get_setup(circuit_id)
+    }
+
+    fn get_vk(&self, _circuit_id: u8) -> Result<ZkSyncVerificationKey<Bn256>, Self::ArtifactError> {
+        Err(std::io::ErrorKind::NotFound.into())
+    }
+}
+
+struct TestingParamsWithRetries;
+
+impl Params for TestingParamsWithRetries {
+    fn number_of_parallel_synthesis(&self) -> u8 {
+        3
+    }
+
+    fn number_of_setup_slots(&self) -> u8 {
+        4
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            retryable: vec![FailureClass::MissingArtifact],
+        }
+    }
+}
+
+#[test]
+fn test_prover_service_retries_failed_proofs() {
+    let jobs = jobs_from_artifacts();
+    // exhausted jobs are not picked again
+    run_service_until(
+        ArtifactManagerWithoutVks,
+        jobs.clone(),
+        TestingParamsWithRetries,
+        |_| {
+            jobs.lock()
+                .unwrap()
+                .iter()
+                .any(|(_, _, state)| matches!(state, JobState::Exhausted(_, 3)))
+        },
+        Duration::from_secs(60),
+    );
+}
+
+#[test]
+fn test_prover_service_gives_up_on_failures_it_doesnt_retry() {
+    let jobs = jobs_from_artifacts();
+    // missing artifacts aren't retryable by default
+    run_service_until(
+        ArtifactManagerWithoutVks,
+        jobs.clone(),
+        TestingParams,
+        |_| {
+            jobs.lock()
+                .unwrap()
+                .iter()
+                .all(|(_, _, state)| matches!(state, JobState::Exhausted(_, 1)))
+        },
+        Duration::from_secs(60),
+    );
+}
+
+#[test]
+fn test_durable_job_manager_recovers_started_jobs() {
+    let artifacts_dir = get_artifacts_dir();
+    let circuits = read_circuits_from_directory(&artifacts_dir);
+    assert!(circuits.len() >= 3);
+
+    let store_dir = std::env::temp_dir().join(format!("durable-jobs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&store_dir);
+    let store = DurableJobStore::open(&store_dir).unwrap();
+    for circuit in circuits.iter().take(3) {
+        store.add_job(circuit).unwrap();
+    }
+    let mut job_manager = DurableJobManager::new(store.clone());
+    let mut job_reporter = DurableJobReporter::new(store.clone());
+    let (failed_job_id, _) = job_manager.try_get_next_job().unwrap();
+    let (started_job_id, circuit) = job_manager.try_get_next_job().unwrap();
+    assert_eq!(
+        circuit.numeric_circuit_type(),
+        circuits[1].numeric_circuit_type()
+    );
+    job_reporter.send_report(JobResult::Failure(
+        failed_job_id,
+        "failed\tproving".to_string(),
+    ));
+    assert!(store.cancel(2).unwrap());
+    // a cancelled job stays cancelled when its running attempt fails late
+    job_reporter.send_report(JobResult::Failure(2, "stage timed out".to_string()));
+    drop((job_manager, job_reporter, store));
+
+    // a crash in the middle of an append leaves a partial record behind
+    let mut log = std::fs::OpenOptions::new()
+        .append(true)
+        .open(store_dir.join("jobs.log"))
+        .unwrap();
+    log.write_all(b"2\t").unwrap();
+    drop(log);
+
+    let store = DurableJobStore::open(&store_dir).unwrap();
+    let mut states = HashMap::new();
+    store.for_each_job(|job_id, _, state| {
+        states.insert(job_id, format!("{:?}", state));
+    });
+    assert_eq!(states[&failed_job_id], "Failure(0, \"failed proving\")");
+    assert_eq!(states[&started_job_id], "Created(1)");
+    assert_eq!(states[&2], "Cancelled(2)");
+
+    // the requeued job goes first, the failed one after it
+    let mut job_manager = DurableJobManager::new(store.clone());
+    assert_eq!(job_manager.try_get_next_job().unwrap().0, started_job_id);
+    assert_eq!(job_manager.try_get_next_job().unwrap().0, failed_job_id);
+    assert!(job_manager.try_get_next_job().is_none());
+    assert!(job_manager.is_cancelled(2));
+
+    std::fs::remove_dir_all(&store_dir).unwrap();
+}
+
+#[test]
+fn test_durable_job_store_notifies_about_new_jobs() {
+    let artifacts_dir = get_artifacts_dir();
+    let circuits = read_circuits_from_directory(&artifacts_dir);
+    assert!(!circuits.is_empty());
+
+    let store_dir =
+        std::env::temp_dir().join(format!("durable-availability-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&store_dir);
+    let store = DurableJobStore::open(&store_dir).unwrap();
+    let mut job_manager = DurableJobManager::new(store.clone());
+    let job_availability = job_manager.job_availability().unwrap();
+    let adding_store = store.clone();
+    let adder = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(50));
+        adding_store.add_job(&circuits[0]).unwrap()
+    });
+    // the recheck interval is far beyond the bound, only the notification wakes the waiter up
+    let started = std::time::Instant::now();
+    let job = job_availability.wait_for(
+        Some(Duration::from_secs(600)),
+        Duration::from_secs(600),
+        || job_manager.try_get_next_job(),
+    );
+    assert_eq!(job.map(|(job_id, _)| job_id), Some(adder.join().unwrap()));
+    assert!(started.elapsed() < Duration::from_secs(60));
+
+    std::fs::remove_dir_all(&store_dir).unwrap();
+}
+
+#[test]
+fn test_directory_job_manager_moves_finished_circuits() {
+    let artifacts_dir = get_artifacts_dir();
+    let circuits = read_circuits_from_directory(&artifacts_dir);
+    assert!(circuits.len() >= 2);
+
+    let drop_dir = std::env::temp_dir().join(format!("drop-folder-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&drop_dir);
+    std::fs::create_dir_all(&drop_dir).unwrap();
+    let poll_interval = Duration::from_millis(50);
+    let mut job_manager = DirectoryJobManager::new(&drop_dir, poll_interval);
+    let mut job_reporter = job_manager.reporter();
+    assert!(job_manager.try_get_next_job().is_none());
+
+    std::fs::write(
+        drop_dir.join("circuit_0.bin"),
+        bincode::serialize(&circuits[0]).unwrap(),
+    )
+    .unwrap();
+    std::fs::write(
+        drop_dir.join("circuit_1.json"),
+        serde_json::to_vec(&circuits[1]).unwrap(),
+    )
+    .unwrap();
+    std::fs::write(drop_dir.join("circuit_2.bin"), b"garbage").unwrap();
+    std::fs::write(drop_dir.join("notes.txt"), b"not a circuit").unwrap();
+    std::thread::sleep(poll_interval * 2);
+
+    let (first_job_id, first_circuit) = job_manager.try_get_next_job().unwrap();
+    assert_eq!(
+        first_circuit.numeric_circuit_type(),
+        circuits[0].numeric_circuit_type()
+    );
+    let (second_job_id, _) = job_manager.try_get_next_job().unwrap();
+    assert!(job_manager.try_get_next_job().is_none());
+
+    // a failure the service may still retry hands the circuit out again
+    job_reporter.send_report(JobResult::Failure(
+        first_job_id,
+        "prover service stopped before the job started".to_string(),
+    ));
+    assert!(drop_dir.join("circuit_0.bin").exists());
+    let (first_job_id, first_circuit) = job_manager.try_get_next_job().unwrap();
+    assert_eq!(
+        first_circuit.numeric_circuit_type(),
+        circuits[0].numeric_circuit_type()
+    );
+    job_reporter.send_report(JobResult::RetriesExhausted(first_job_id, 1));
+    job_reporter.send_report(JobResult::Failure(
+        first_job_id,
+        "proving failed".to_string(),
+    ));
+    job_reporter.send_report(JobResult::Cancelled(second_job_id));
+
+    let failed_dir = drop_dir.join("failed");
+    assert_eq!(
+        std::fs::read_to_string(failed_dir.join("circuit_0.bin.error")).unwrap(),
+        "proving failed"
+    );
+    assert_eq!(
+        std::fs::read_to_string(failed_dir.join("circuit_1.json.error")).unwrap(),
+        "cancelled"
+    );
+    assert!(failed_dir.join("circuit_0.bin").exists());
+    assert!(failed_dir.join("circuit_1.json").exists());
+    assert!(
+        std::fs::read_to_string(failed_dir.join("circuit_2.bin.error"))
+            .unwrap()
+            .starts_with("decoding failed")
+    );
+    assert!(!drop_dir.join("circuit_0.bin").exists());
+    assert!(drop_dir.join("notes.txt").exists());
+
+    // moved files are not picked up again
+    std::thread::sleep(poll_interval * 2);
+    assert!(job_manager.try_get_next_job().is_none());
+
+    std::fs::remove_dir_all(&drop_dir).unwrap();
+}
+
+#[test]
+fn test_simple_job_manager_waits_for_jobs() {
+    let artifacts_dir = get_artifacts_dir();
+    let circuits = read_circuits_from_directory(&artifacts_dir);
+    assert!(!circuits.is_empty());
+
+    let jobs = Arc::new(Mutex::new(vec![]));
+    let mut job_manager = SimpleJobManager::new(jobs.clone());
+    let job_availability = job_manager.job_availability().unwrap();
+    let job_reporter =
+        SimpleJobReporter::new(jobs.clone()).with_job_availability(job_availability.clone());
+
+    let started = std::time::Instant::now();
+    assert!(job_manager
+        .get_next_job_timeout(Duration::from_millis(200))
+        .is_none());
+    assert!(started.elapsed() >= Duration::from_millis(200));
+
+    let producer = {
+        let jobs = jobs.clone();
+        let circuit = circuits[0].clone();
+        let job_availability = job_availability.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            jobs.lock()
+                .unwrap()
+                .push((0, circuit, JobState::Created(0)));
+            job_availability.notify();
+        })
+    };
+    // the recheck interval is far beyond the bound, only the notification wakes the waiter up
+    let started = std::time::Instant::now();
+    let job = job_availability.wait_for(
+        Some(Duration::from_secs(600)),
+        Duration::from_secs(600),
+        || job_manager.try_get_next_job(),
+    );
+    assert_eq!(job.map(|(job_id, _)| job_id), Some(0));
+    assert!(started.elapsed() < Duration::from_secs(60));
+    producer.join().unwrap();
+
+    // a failed job becomes pickable again, the report wakes up the waiting caller
+    let reporter = {
+        let mut job_reporter = job_reporter;
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            job_reporter.send_report(JobResult::Failure(0, "failed".to_string()));
+        })
+    };
+    let started = std::time::Instant::now();
+    let job = job_availability.wait_for(
+        Some(Duration::from_secs(600)),
+        Duration::from_secs(600),
+        || job_manager.try_get_next_job(),
+    );
+    assert_eq!(job.map(|(job_id, _)| job_id), Some(0));
+    assert!(started.elapsed() < Duration::from_secs(60));
+    reporter.join().unwrap();
+}
+
+#[test]
+fn test_worker_pool_survives_panicking_tasks() {
+    let pool = WorkerPool::new(
+        "test",
+        WorkerPoolConfig {
+            num_workers: 2,
+            queue_depth: 4,
+        },
+    );
+    let (sender, receiver) = channel();
+    for idx in 0..8 {
+        let sender = sender.clone();
+        pool.execute(move || {
+            if idx % 4 == 0 {
+                panic!("task {} failed", idx);
+            }
+            sender.send(idx).unwrap();
+        });
+    }
+    for _ in 0..6 {
+        receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+    pool.shutdown(true);
+
+    assert_eq!(
+        pool.stats(),
+        WorkerPoolStats {
+            queued: 0,
+            active: 0,
+            completed: 8,
+            panicked: 2,
+        }
+    );
+}
+
+#[test]
+fn test_worker_pool_replaces_stuck_worker() {
+    let pool = WorkerPool::new(
+        "test",
+        WorkerPoolConfig {
+            num_workers: 1,
+            queue_depth: 1,
+        },
+    );
+    let (unblock_sender, unblock_receiver) = channel::<()>();
+    pool.execute(move || unblock_receiver.recv().unwrap());
+    let (sender, receiver) = channel();
+    pool.execute(move || sender.send(()).unwrap());
+    // the only worker is stuck
+    assert!(receiver.recv_timeout(Duration::from_millis(100)).is_err());
+
+    assert!(pool.replace_stuck_worker());
+    // one outstanding replacement per worker
+    assert!(!pool.replace_stuck_worker());
+    receiver.recv_timeout(Duration::from_secs(10)).unwrap();
+    unblock_sender.send(()).unwrap();
+    pool.shutdown(true);
+    assert_eq!(pool.stats().completed, 2);
+}
+
+struct TestingParamsWithSerialSynthesis;
+
+impl Params for TestingParamsWithSerialSynthesis {
+    fn number_of_parallel_synthesis(&self) -> u8 {
+        3
+    }
+
+    fn number_of_setup_slots(&self) -> u8 {
+        4
+    }
+
+    fn worker_pool_config(&self, kind: WorkerPoolKind) -> WorkerPoolConfig {
+        let num_workers = match kind {
+            WorkerPoolKind::Synthesis => 1,
+            _ => 3,
+        };
+        WorkerPoolConfig {
+            num_workers,
+            queue_depth: 3,
+        }
+    }
+}
+
+#[test]
+fn test_prover_service_schedules_high_priority_jobs_first() {
+    let params = TestingParamsWithSerialSynthesis;
+    let jobs = jobs_from_artifacts();
+    // every job fits into the lookahead, which is bounded by the free assemblies
+    jobs.lock()
+        .unwrap()
+        .truncate(params.number_of_parallel_synthesis() as usize);
+    let circuit_ids: HashMap<usize, u8> = jobs
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(job_id, c, _)| (*job_id, c.numeric_circuit_type()))
+        .collect();
+    assert!(circuit_ids.len() > 1);
+    // the last job would be picked last without priorities
+    let urgent_circuit_id = circuit_ids[&(circuit_ids.len() - 1)];
+
+    let job_manager = SimpleJobManager::new(jobs.clone())
+        .with_priorities(HashMap::from([(urgent_circuit_id, 10)]));
+    let reports = Arc::new(Mutex::new(vec![]));
+    let job_reporter = CollectingJobReporter(reports.clone());
+    let artifact_manager = SimpleArtifactManager;
+
+    let shutdown_handle = run_prover_with_local_synthesizer(
+        artifact_manager,
+        job_manager,
+        job_reporter,
+        None,
+        params,
+    );
+    assert!(wait_until(Duration::from_secs(300), || reports
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|report| matches!(report, JobResult::Synthesized(..)))));
+    assert!(shutdown_handle.shutdown(Duration::from_secs(60)));
+
+    let reports = reports.lock().unwrap();
+    let first_synthesized = reports
+        .iter()
+        .find_map(|report| match report {
+            JobResult::Synthesized(job_id, _) => Some(*job_id),
+            _ => None,
+        })
+        .expect("no job was synthesized");
+    assert_eq!(circuit_ids[&first_synthesized], urgent_circuit_id);
 }
 
 #[test]
@@ -185,7 +1251,8 @@ fn test_prover_service_with_external_synthesizer_and_shuffled_circuits() {
         job_reporter,
         Some(circuit_ids),
         TestingParams,
-    );
+    )
+    .wait();
 }
 #[test]
 fn test_generic_prover_with_external_synthesizer_and_shuffled_circuits() {
@@ -249,7 +1316,8 @@ fn test_generic_prover_with_external_synthesizer_and_shuffled_circuits() {
         job_reporter,
         None,
         params,
-    );
+    )
+    .wait();
 }
 
 #[test]
@@ -344,7 +1412,8 @@ fn test_specialized_prover_server() {
         job_reporter,
         Some(circuit_ids),
         params,
-    );
+    )
+    .wait();
 }
 
 #[test]
@@ -411,6 +1480,11 @@ fn test_run_remote_synthesizer() {
         fn number_of_setup_slots(&self) -> u8 {
             0
         }
+        fn artifact_router(&self) -> Box<dyn ArtifactRouter> {
+            // first server is specialized in the first 10 circuits
+            let routes = (0..10).map(|circuit_id| (circuit_id, 0)).collect();
+            Box::new(StaticRouter::new(routes, Some(1)))
+        }
     }
 
     let params = TestingParamsForSynthesizer;
@@ -456,6 +1530,243 @@ fn test_run_remote_synthesizer() {
     run_remote_synthesizer(job_manager, job_reporter, artifact_senders, params);
 }
 
+#[test]
+fn test_tcp_transport_delivers_artifacts() {
+    let config = TcpTransportConfig {
+        max_frame_len: Some(1 << 20),
+        ..Default::default()
+    };
+    let mut remote_synthesizer = TcpRemoteSynthesizer::bind("127.0.0.1:0", config.clone()).unwrap();
+    let mut artifact_sender =
+        TcpArtifactSender::new(remote_synthesizer.local_addr(), config).unwrap();
+
+    let artifacts: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![42; 13],
+        (0..1 << 16).map(|i| i as u8).collect(),
+    ];
+    for (circuit_id, artifact) in artifacts.iter().enumerate() {
+        artifact_sender
+            .send(Box::new(Cursor::new(artifact.clone())), circuit_id as u8)
+            .unwrap();
+        artifact_sender
+            .wait_for_acknowledgement(circuit_id as u8)
+            .unwrap();
+    }
+
+    for expected in artifacts.iter() {
+        let start = std::time::Instant::now();
+        let mut encoding = loop {
+            if let Some(encoding) = remote_synthesizer.try_next() {
+                break encoding;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        let mut actual = vec![];
+        encoding.read_to_end(&mut actual).unwrap();
+        assert_eq!(expected, &actual);
+    }
+}
+
+#[test]
+fn test_tcp_transport_exchanges_credits() {
+    let config = TcpTransportConfig {
+        max_frame_len: Some(1 << 20),
+        ..Default::default()
+    };
+    let mut remote_synthesizer = TcpRemoteSynthesizer::bind("127.0.0.1:0", config.clone()).unwrap();
+    let mut artifact_sender =
+        TcpArtifactSender::new(remote_synthesizer.local_addr(), config).unwrap();
+    assert_eq!(artifact_sender.credits(), None);
+
+    for credits in [
+        Credits {
+            free_slots: 2,
+            circuit_ids: Some(vec![0, 3, 7]),
+        },
+        Credits {
+            free_slots: 0,
+            circuit_ids: None,
+        },
+    ] {
+        remote_synthesizer.advertise_credits(credits.clone());
+        assert_eq!(artifact_sender.credits(), Some(credits));
+    }
+
+    // credits are still served between artifacts on the same connection
+    artifact_sender
+        .send(Box::new(Cursor::new(vec![1, 2, 3])), 0)
+        .unwrap();
+    assert_eq!(
+        artifact_sender.credits().map(|credits| credits.free_slots),
+        Some(0)
+    );
+    assert!(remote_synthesizer.try_next().is_some());
+}
+
+#[test]
+fn test_tcp_artifact_sender_fails_without_server() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let config = TcpTransportConfig {
+        connect_timeout: Duration::from_millis(100),
+        max_frame_len: Some(1 << 20),
+        ..Default::default()
+    };
+    let mut artifact_sender = TcpArtifactSender::new(addr, config).unwrap();
+    assert!(artifact_sender
+        .send(Box::new(Cursor::new(vec![1, 2, 3])), 0)
+        .is_err());
+    assert_eq!(
+        artifact_sender.credits().map(|credits| credits.free_slots),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_artifact_delivery_retries_unacknowledged_encodings() {
+    struct FlakyArtifactSender {
+        num_failures: usize,
+        delivered: Vec<Vec<u8>>,
+    }
+
+    impl EncodedArtifactSender for FlakyArtifactSender {
+        fn send(
+            &mut self,
+            mut encoded_artifact: Box<dyn Read>,
+            _circuit_id: u8,
+        ) -> Result<(), std::io::Error> {
+            let mut encoding = vec![];
+            encoded_artifact.read_to_end(&mut encoding)?;
+            self.delivered.push(encoding);
+            Ok(())
+        }
+
+        fn wait_for_acknowledgement(&mut self, _circuit_id: u8) -> Result<(), std::io::Error> {
+            if self.num_failures > 0 {
+                self.num_failures -= 1;
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            Ok(())
+        }
+    }
+
+    let encoding = Arc::new(vec![7u8; 1024]);
+    let backoff = Duration::from_millis(1);
+
+    let mut artifact_sender = FlakyArtifactSender {
+        num_failures: 2,
+        delivered: vec![],
+    };
+    let mut retries = vec![];
+    deliver_encoding(
+        &mut artifact_sender,
+        &encoding,
+        0,
+        2,
+        backoff,
+        |attempt, _| retries.push(attempt),
+    )
+    .unwrap();
+    assert_eq!(artifact_sender.delivered.len(), 3);
+    assert_eq!(retries, vec![1, 2]);
+    assert!(artifact_sender
+        .delivered
+        .iter()
+        .all(|delivered| delivered == encoding.as_ref()));
+
+    let mut artifact_sender = FlakyArtifactSender {
+        num_failures: 3,
+        delivered: vec![],
+    };
+    let result = deliver_encoding(&mut artifact_sender, &encoding, 0, 2, backoff, |_, _| ());
+    assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+    assert_eq!(artifact_sender.delivered.len(), 3);
+}
+
+#[test]
+fn test_routing_respects_credits() {
+    let routes = HashMap::from([(1, 0), (2, 1), (3, 0)]);
+    let ctx = SynthesizerContext::new(2, Box::new(StaticRouter::new(routes, None)));
+    ctx.update_credits(
+        0,
+        Some(Credits {
+            free_slots: 2,
+            circuit_ids: Some(vec![1]),
+        }),
+    );
+    ctx.update_credits(
+        1,
+        Some(Credits {
+            free_slots: 1,
+            circuit_ids: Some(vec![2]),
+        }),
+    );
+
+    // the router's choice stands, its server is waited for once its slots are taken
+    assert_eq!(ctx.route(2), Some(1));
+    assert!(ctx.take_credit(1));
+    assert_eq!(ctx.route(2), Some(1));
+    assert!(!ctx.take_credit(1));
+    assert_eq!(ctx.route(1), Some(0));
+    assert!(ctx.take_credit(0));
+    assert!(ctx.take_credit(0));
+    assert!(!ctx.take_credit(0));
+    // unmapped circuits and servers that don't accept the circuit type have no route
+    assert_eq!(ctx.route(4), None);
+    assert_eq!(ctx.route(3), None);
+
+    // the assembly still queued for the second server keeps its credit taken
+    ctx.update_credits(
+        1,
+        Some(Credits {
+            free_slots: 1,
+            circuit_ids: Some(vec![2]),
+        }),
+    );
+    assert!(!ctx.take_credit(1));
+}
+
+#[test]
+fn test_artifact_routers() {
+    let mut static_router = StaticRouter::new(HashMap::from([(3, 1)]), Some(0));
+    assert_eq!(static_router.route(3, &[0, 0]), Some(1));
+    assert_eq!(static_router.route(4, &[0, 0]), Some(0));
+    let mut static_router = StaticRouter::new(HashMap::from([(3, 1)]), None);
+    assert_eq!(static_router.route(4, &[0, 0]), None);
+
+    let mut round_robin_router = RoundRobinRouter::default();
+    let routes: Vec<_> = (0..5)
+        .map(|circuit_id| round_robin_router.route(circuit_id, &[0, 0, 0]))
+        .collect();
+    assert_eq!(routes, vec![Some(0), Some(1), Some(2), Some(0), Some(1)]);
+    assert_eq!(round_robin_router.route(0, &[]), None);
+
+    let mut least_loaded_router = LeastLoadedRouter;
+    assert_eq!(least_loaded_router.route(0, &[2, 1, 1]), Some(1));
+    assert_eq!(least_loaded_router.route(0, &[]), None);
+
+    let mut consistent_hash_router = ConsistentHashRouter::new(3, 16);
+    for circuit_id in 0..20 {
+        let sender_idx = consistent_hash_router.route(circuit_id, &[0, 0, 0]);
+        assert!(matches!(sender_idx, Some(idx) if idx < 3));
+        assert_eq!(
+            sender_idx,
+            consistent_hash_router.route(circuit_id, &[5, 0, 0])
+        );
+    }
+    // a new server only takes over circuits, it never moves them between existing ones
+    let mut extended_router = ConsistentHashRouter::new(4, 16);
+    for circuit_id in 0..20 {
+        let before = consistent_hash_router.route(circuit_id, &[0, 0, 0]);
+        let after = extended_router.route(circuit_id, &[0, 0, 0, 0]);
+        assert!(after == before || after == Some(3));
+    }
+}
+
 #[test]
 fn generate_setup_and_vk_from_json_file() {
     assert!(std::env::var("CRS_FILE").is_ok());
@@ -839,7 +2150,7 @@ fn test_custom_serialization_of_multiple_assemblies() {
 
                     let mut decoded_assembly = Prover::new_proving_assembly();
                     let mut buffer = Cursor::new(assembly_encoding);
-                    deserialize_job(&mut buffer, &mut decoded_assembly);
+                    deserialize_job(&mut buffer, &mut decoded_assembly).unwrap();
 
                     compare_assemblies(&assembly, &decoded_assembly);
 
@@ -857,42 +2168,229 @@ fn test_custom_serialization_of_multiple_assemblies() {
 }
 
 #[test]
-fn test_custom_serialization_of_assembly() {
+fn test_corrupted_assembly_encoding_is_rejected() {
     let circuit_file = std::env::var("CIRCUIT_FILE").unwrap();
     let circuit = decode_circuit_from_file(&circuit_file.into());
-    let circuit_id = circuit.numeric_circuit_type();
 
-    println!(
-        "{} {} synthesizing",
-        circuit.numeric_circuit_type(),
-        circuit.short_description()
-    );
+    let mut assembly = prover::Prover::new_proving_assembly();
+    circuit.synthesize(&mut assembly).unwrap();
+
+    let mut buffer = Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
+    serialize_job(&assembly, 42, circuit.numeric_circuit_type(), &mut buffer);
+
+    // last byte of the payload right before the checksum trailer
+    let trailer_len = 4 * 8;
+    let idx = buffer.len() - trailer_len - 1;
+    buffer[idx] ^= 0xff;
+
+    let mut decoded_assembly = Prover::new_proving_assembly();
+    let result = deserialize_job(&mut Cursor::new(buffer), &mut decoded_assembly);
+    match result {
+        Err(DecodeError::SectionChecksumMismatch {
+            section: Section::Tables,
+            ..
+        }) => (),
+        Err(e) => panic!("unexpected error {:?}", e),
+        Ok(_) => panic!("corrupted encoding has been accepted"),
+    }
+}
+
+#[test]
+fn test_serialized_job_round_trips() {
+    let circuit_file = std::env::var("CIRCUIT_FILE").unwrap();
+    let circuit = decode_circuit_from_file(&circuit_file.into());
+    let circuit_id = circuit.numeric_circuit_type();
 
     let mut expected_assembly = prover::Prover::new_proving_assembly();
     circuit.synthesize(&mut expected_assembly).unwrap();
 
+    let mut dense_buffer =
+        Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
+    custom_assembly_serialization(&expected_assembly, &mut dense_buffer);
+
+    for (compression, column_encoding) in [
+        (Compression::None, ColumnEncoding::Dense),
+        (Compression::Zstd, ColumnEncoding::Dense),
+        (Compression::Lz4, ColumnEncoding::Dense),
+        (Compression::None, ColumnEncoding::RunLength),
+        (Compression::Zstd, ColumnEncoding::RunLength),
+    ] {
+        let options = EncodingOptions {
+            compression,
+            column_encoding,
+        };
+        let mut buffer =
+            Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
+        serialize_job_with_options(&expected_assembly, 42, circuit_id, &options, &mut buffer);
+        println!(
+            "{} {:?} encoding takes {} bytes",
+            circuit.short_description(),
+            options,
+            buffer.len()
+        );
+
+        let mut actual_assembly = Prover::new_proving_assembly();
+        let decoded = deserialize_job(&mut Cursor::new(buffer), &mut actual_assembly);
+        assert_eq!(decoded.unwrap(), (42, circuit_id), "{:?}", options);
+        compare_assemblies(&expected_assembly, &actual_assembly);
+
+        // decoded assembly re-encodes into exactly the same dense bytes
+        let mut reencoded_buffer =
+            Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
+        custom_assembly_serialization(&actual_assembly, &mut reencoded_buffer);
+        assert!(dense_buffer == reencoded_buffer, "{:?}", options);
+    }
+}
+
+#[test]
+fn test_truncated_assembly_encoding_is_rejected() {
+    let circuit_file = std::env::var("CIRCUIT_FILE").unwrap();
+    let circuit = decode_circuit_from_file(&circuit_file.into());
+
+    let mut assembly = prover::Prover::new_proving_assembly();
+    circuit.synthesize(&mut assembly).unwrap();
+
     let mut buffer = Vec::with_capacity(calculate_serialization_capacity_for_proving_assembly());
-    let start = std::time::Instant::now();
-    custom_assembly_serialization(&expected_assembly, &mut buffer);
-    println!(
-        "{} {} custom serialization takes {:?}",
-        circuit.numeric_circuit_type(),
-        circuit.short_description(),
-        start.elapsed()
-    );
+    serialize_job(&assembly, 42, circuit.numeric_circuit_type(), &mut buffer);
+    buffer.truncate(buffer.len() / 2);
 
-    let mut actual_assembly = Prover::new_proving_assembly();
-    let start = std::time::Instant::now();
-    let mut encoding = Cursor::new(buffer);
-    custom_assembly_deserialization(&mut encoding, &mut actual_assembly);
-    println!(
-        "{} {} custom deserialization takes {:?}",
-        circuit.numeric_circuit_type(),
-        circuit.short_description(),
-        start.elapsed()
-    );
+    let mut decoded_assembly = Prover::new_proving_assembly();
+    let result = deserialize_job(&mut Cursor::new(buffer), &mut decoded_assembly);
+    assert!(matches!(result, Err(DecodeError::Truncated)));
+}
+
+#[test]
+fn test_oversized_assembly_encoding_is_rejected() {
+    let header = EncodingHeader {
+        version: ENCODING_FORMAT_VERSION,
+        domain_size_log: Prover::get_max_domain_size_log() as u8,
+        field_id: BN256_FIELD_ID,
+        compression: Compression::None,
+        column_encoding: ColumnEncoding::Dense,
+        section_lengths: [0, 0, 0],
+    };
+    let mut encoding = vec![];
+    header.write(&mut encoding).unwrap();
+    // num_aux_gates, num_input_gates, num_inputs, num_aux
+    for _ in 0..4 {
+        encoding.extend_from_slice(&8usize.to_le_bytes());
+        encoding.extend_from_slice(&0usize.to_le_bytes());
+    }
+    // empty input assignments
+    encoding.extend_from_slice(&0usize.to_le_bytes());
+    // aux assignments claiming more elements than the pinned buffer holds
+    let num_aux_assignments = Prover::get_max_num_variables() + 1;
+    encoding.extend_from_slice(&(num_aux_assignments * 32).to_le_bytes());
 
-    compare_assemblies(&expected_assembly, &actual_assembly);
+    let mut assembly = Prover::new_proving_assembly();
+    let capacity = assembly.aux_assingments.capacity();
+    let result = custom_assembly_deserialization(&mut Cursor::new(encoding), &mut assembly);
+    assert!(matches!(
+        result,
+        Err(DecodeError::CapacityOverflow { requested, .. }) if requested == num_aux_assignments
+    ));
+    assert_eq!(assembly.aux_assingments.capacity(), capacity);
+}
+
+#[test]
+fn test_assembly_encoding_beyond_buffer_capacity_is_rejected() {
+    let header = EncodingHeader {
+        version: ENCODING_FORMAT_VERSION,
+        domain_size_log: Prover::get_max_domain_size_log() as u8,
+        field_id: BN256_FIELD_ID,
+        compression: Compression::None,
+        column_encoding: ColumnEncoding::Dense,
+        section_lengths: [0, 0, 0],
+    };
+    let mut assembly = Prover::new_proving_assembly();
+    assembly.aux_assingments.shrink_to(1 << 10);
+    let capacity = assembly.aux_assingments.capacity();
+    assert!(capacity < Prover::get_max_num_variables());
+
+    let mut encoding = vec![];
+    header.write(&mut encoding).unwrap();
+    // num_aux_gates, num_input_gates, num_inputs, num_aux
+    for _ in 0..4 {
+        encoding.extend_from_slice(&8usize.to_le_bytes());
+        encoding.extend_from_slice(&0usize.to_le_bytes());
+    }
+    // empty input assignments
+    encoding.extend_from_slice(&0usize.to_le_bytes());
+    // aux assignments within the maximum but beyond the preallocated buffer
+    let num_aux_assignments = capacity + 1;
+    encoding.extend_from_slice(&(num_aux_assignments * 32).to_le_bytes());
+
+    let result = custom_assembly_deserialization(&mut Cursor::new(encoding), &mut assembly);
+    assert!(matches!(
+        result,
+        Err(DecodeError::CapacityOverflow { requested, capacity: limit })
+            if requested == num_aux_assignments && limit == capacity
+    ));
+    assert_eq!(assembly.aux_assingments.capacity(), capacity);
+}
+
+#[test]
+fn test_encoding_header_rejects_incompatible_payloads() {
+    let header = EncodingHeader {
+        version: ENCODING_FORMAT_VERSION,
+        domain_size_log: Prover::get_max_domain_size_log() as u8,
+        field_id: BN256_FIELD_ID,
+        compression: Compression::None,
+        column_encoding: ColumnEncoding::Dense,
+        section_lengths: [1, 2, 3],
+    };
+    let mut encoding = vec![];
+    header.write(&mut encoding).unwrap();
+    let decoded = EncodingHeader::read(&mut Cursor::new(encoding.clone())).unwrap();
+    assert_eq!(header, decoded);
+
+    let mut bad_magic = encoding.clone();
+    bad_magic[0] ^= 0xff;
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_magic)),
+        Err(DecodeError::InvalidMagic(_))
+    ));
+
+    let mut bad_version = encoding.clone();
+    bad_version[4] = bad_version[4].wrapping_add(1);
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_version)),
+        Err(DecodeError::UnsupportedVersion(_))
+    ));
+
+    let mut bad_domain = encoding.clone();
+    bad_domain[6] = bad_domain[6].wrapping_add(1);
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_domain)),
+        Err(DecodeError::DomainSizeMismatch { .. })
+    ));
+
+    let mut bad_field = encoding.clone();
+    bad_field[7] = bad_field[7].wrapping_add(1);
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_field)),
+        Err(DecodeError::FieldMismatch { .. })
+    ));
+
+    let mut bad_compression = encoding.clone();
+    bad_compression[8] = u8::MAX;
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_compression)),
+        Err(DecodeError::UnsupportedCompression(u8::MAX))
+    ));
+
+    let mut bad_column_encoding = encoding.clone();
+    bad_column_encoding[9] = u8::MAX;
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(bad_column_encoding)),
+        Err(DecodeError::UnsupportedColumnEncoding(u8::MAX))
+    ));
+
+    let truncated = encoding[..encoding.len() - 1].to_vec();
+    assert!(matches!(
+        EncodingHeader::read(&mut Cursor::new(truncated)),
+        Err(DecodeError::Truncated)
+    ));
 }
 
 fn compare_assemblies(this: &ProvingAssembly, other: &ProvingAssembly) {
@@ -976,7 +2474,7 @@ fn test_create_proof_for_deserialized_assembly() {
     let assembly_file_path = std::env::var("ASSEMBLY_FILE").unwrap();
     let mut assembly_file = std::fs::File::open(&assembly_file_path).unwrap();
     let mut assembly = Prover::new_proving_assembly();
-    let (job_id, circuit_id) = deserialize_job(&mut assembly_file, &mut assembly);
+    let (job_id, circuit_id) = deserialize_job(&mut assembly_file, &mut assembly).unwrap();
 
     for (table_name, table) in assembly.individual_table_canonical_sorted_entries.iter() {
         let num_rows_of_witnesses = assembly
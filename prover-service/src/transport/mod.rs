@@ -0,0 +1,5 @@
+use super::*;
+
+pub mod tcp;
+
+pub use tcp::*;
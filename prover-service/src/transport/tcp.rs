@@ -0,0 +1,384 @@
+use std::{
+    io::{Cursor, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::mpsc::{Receiver, SyncSender},
+};
+
+use super::*;
+use crate::remote_synth::{
+    calculate_serialization_capacity_for_proving_assembly, EncodedArtifactSender,
+};
+
+// every frame is [kind u8][payload length u64][payload]
+const FRAME_KIND_ARTIFACT: u8 = 1;
+const FRAME_KIND_ACK: u8 = 2;
+const FRAME_KIND_CREDITS_REQUEST: u8 = 3;
+const FRAME_KIND_CREDITS: u8 = 4;
+// acks and credits are all the sender ever reads
+const MAX_CONTROL_FRAME_LEN: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub struct TcpTransportConfig {
+    pub connect_timeout: Duration,
+    /// Timeout of socket writes on both ends.
+    pub write_timeout: Duration,
+    /// How long the sender waits for an acknowledgement, it includes the time
+    /// the artifact waits for a free slot in the server queue.
+    pub ack_timeout: Duration,
+    /// Number of received artifacts the server buffers before it stops reading.
+    pub queue_bound: usize,
+    /// Upper bound of a single artifact, larger frames are refused. `None` stands for
+    /// the serialization capacity of a proving assembly, computed once the server binds.
+    pub max_frame_len: Option<usize>,
+}
+
+impl Default for TcpTransportConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(5),
+            write_timeout: Duration::from_secs(60),
+            ack_timeout: Duration::from_secs(300),
+            queue_bound: 4,
+            max_frame_len: None,
+        }
+    }
+}
+
+fn write_frame<W: Write>(stream: &mut W, kind: u8, payload: &[u8]) -> std::io::Result<()> {
+    write_frame_from(stream, kind, payload.len(), &mut &payload[..])
+}
+
+/// Streams a payload of known length into the frame without buffering it.
+fn write_frame_from<W: Write, R: Read>(
+    stream: &mut W,
+    kind: u8,
+    len: usize,
+    payload: &mut R,
+) -> std::io::Result<()> {
+    stream.write_all(&[kind])?;
+    stream.write_all(&(len as u64).to_le_bytes())?;
+    let written = std::io::copy(&mut payload.take(len as u64), stream)?;
+    if written != len as u64 {
+        // the peer can't tell the rest of the frame from the next one, drop the connection
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            format!("payload ended after {} of {} bytes", written, len),
+        ));
+    }
+    stream.flush()
+}
+
+/// Returns `None` if the peer closed the connection between frames.
+fn read_frame<R: Read>(
+    stream: &mut R,
+    max_frame_len: usize,
+) -> std::io::Result<Option<(u8, Vec<u8>)>> {
+    let mut kind = [0u8; 1];
+    if stream.read(&mut kind)? == 0 {
+        return Ok(None);
+    }
+    let mut len = [0u8; 8];
+    stream.read_exact(&mut len)?;
+    let len = u64::from_le_bytes(len) as usize;
+    if len > max_frame_len {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds limit of {}", len, max_frame_len),
+        ));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload)?;
+
+    Ok(Some((kind[0], payload)))
+}
+
+/// Prover side of the transport. Accepts persistent connections from
+/// `TcpArtifactSender`s and queues every received encoding for the prover.
+pub struct TcpRemoteSynthesizer {
+    receiver: Receiver<Box<dyn Read + Send + Sync>>,
+    local_addr: SocketAddr,
+    credits: Arc<Mutex<Option<Credits>>>,
+}
+
+impl TcpRemoteSynthesizer {
+    pub fn bind<A: ToSocketAddrs>(
+        addr: A,
+        mut config: TcpTransportConfig,
+    ) -> std::io::Result<Self> {
+        config.max_frame_len = Some(
+            config
+                .max_frame_len
+                .unwrap_or_else(calculate_serialization_capacity_for_proving_assembly),
+        );
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let (sender, receiver) = std::sync::mpsc::sync_channel(config.queue_bound);
+        let credits = Arc::new(Mutex::new(None));
+        let advertised_credits = credits.clone();
+        println!("server started on {}", local_addr);
+        std::thread::spawn(move || {
+            for conn in listener.incoming() {
+                match conn {
+                    Ok(stream) => {
+                        let sender = sender.clone();
+                        let credits = advertised_credits.clone();
+                        let config = config.clone();
+                        std::thread::spawn(move || {
+                            handle_connection(stream, sender, credits, config)
+                        });
+                    }
+                    Err(e) => println!("failed accepting connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            receiver,
+            local_addr,
+            credits,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+impl RemoteSynthesizer for TcpRemoteSynthesizer {
+    fn try_next(&mut self) -> Option<Box<dyn Read + Send + Sync>> {
+        self.receiver.try_recv().ok()
+    }
+
+    fn advertise_credits(&mut self, credits: Credits) {
+        *self.credits.lock().unwrap() = Some(credits);
+    }
+}
+
+// credits are encoded as [free slots u64][has circuit ids u8][circuit ids], no credits as empty payload
+fn encode_credits(credits: &Option<Credits>) -> Vec<u8> {
+    let mut encoding = vec![];
+    if let Some(credits) = credits {
+        encoding.extend_from_slice(&(credits.free_slots as u64).to_le_bytes());
+        match credits.circuit_ids {
+            Some(ref circuit_ids) => {
+                encoding.push(1);
+                encoding.extend_from_slice(circuit_ids);
+            }
+            None => encoding.push(0),
+        }
+    }
+    encoding
+}
+
+fn decode_credits(encoding: &[u8]) -> std::io::Result<Option<Credits>> {
+    if encoding.is_empty() {
+        return Ok(None);
+    }
+    if encoding.len() < 9 || encoding[8] > 1 || (encoding[8] == 0 && encoding.len() > 9) {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "malformed credits",
+        ));
+    }
+    let mut free_slots = [0u8; 8];
+    free_slots.copy_from_slice(&encoding[..8]);
+    let circuit_ids = if encoding[8] == 1 {
+        Some(encoding[9..].to_vec())
+    } else {
+        None
+    };
+
+    Ok(Some(Credits {
+        free_slots: u64::from_le_bytes(free_slots) as usize,
+        circuit_ids,
+    }))
+}
+
+fn handle_connection(
+    mut stream: TcpStream,
+    sender: SyncSender<Box<dyn Read + Send + Sync>>,
+    credits: Arc<Mutex<Option<Credits>>>,
+    config: TcpTransportConfig,
+) {
+    let peer_addr = stream.peer_addr().ok();
+    if let Err(e) = stream
+        .set_nodelay(true)
+        .and_then(|_| stream.set_write_timeout(Some(config.write_timeout)))
+    {
+        println!("failed configuring connection from {:?}: {}", peer_addr, e);
+        return;
+    }
+    loop {
+        let max_frame_len = config.max_frame_len.expect("resolved on bind");
+        let payload = match read_frame(&mut stream, max_frame_len) {
+            Ok(Some((FRAME_KIND_ARTIFACT, payload))) => payload,
+            Ok(Some((FRAME_KIND_CREDITS_REQUEST, _))) => {
+                let encoding = encode_credits(&credits.lock().unwrap());
+                if let Err(e) = write_frame(&mut stream, FRAME_KIND_CREDITS, &encoding) {
+                    println!("failed sending credits to {:?}: {}", peer_addr, e);
+                    return;
+                }
+                continue;
+            }
+            Ok(Some((kind, _))) => {
+                println!("unexpected frame {} from {:?}", kind, peer_addr);
+                return;
+            }
+            Ok(None) => return,
+            Err(e) => {
+                println!("failed reading frame from {:?}: {}", peer_addr, e);
+                return;
+            }
+        };
+        println!("received assembly encoding from {:?}", peer_addr);
+        // blocks while the queue is full which in turn delays the acknowledgement
+        if sender.send(Box::new(Cursor::new(payload))).is_err() {
+            return;
+        }
+        if let Err(e) = write_frame(&mut stream, FRAME_KIND_ACK, &[]) {
+            println!("failed acknowledging frame to {:?}: {}", peer_addr, e);
+            return;
+        }
+    }
+}
+
+/// Synthesizer side of the transport. Keeps a single connection to the prover
+/// server and reconnects on the next `send` once it broke. An artifact is
+/// delivered when `wait_for_acknowledgement` returns successfully.
+pub struct TcpArtifactSender {
+    addr: SocketAddr,
+    config: TcpTransportConfig,
+    stream: Option<TcpStream>,
+    pending_ack: bool,
+}
+
+impl TcpArtifactSender {
+    pub fn new<A: ToSocketAddrs>(addr: A, config: TcpTransportConfig) -> std::io::Result<Self> {
+        let addr = addr.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "no address resolved")
+        })?;
+
+        Ok(Self {
+            addr,
+            config,
+            stream: None,
+            pending_ack: false,
+        })
+    }
+
+    fn connect(&mut self) -> std::io::Result<&mut TcpStream> {
+        if self.stream.is_none() {
+            let stream = TcpStream::connect_timeout(&self.addr, self.config.connect_timeout)?;
+            stream.set_nodelay(true)?;
+            stream.set_write_timeout(Some(self.config.write_timeout))?;
+            stream.set_read_timeout(Some(self.config.ack_timeout))?;
+            self.stream = Some(stream);
+        }
+
+        Ok(self.stream.as_mut().unwrap())
+    }
+
+    fn read_ack(&mut self) -> std::io::Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotConnected))?;
+        read_expected_frame(stream, FRAME_KIND_ACK, MAX_CONTROL_FRAME_LEN)?;
+        Ok(())
+    }
+
+    fn request_credits(&mut self) -> std::io::Result<Option<Credits>> {
+        let stream = self.connect()?;
+        write_frame(stream, FRAME_KIND_CREDITS_REQUEST, &[])?;
+        let encoding = read_expected_frame(stream, FRAME_KIND_CREDITS, MAX_CONTROL_FRAME_LEN)?;
+        decode_credits(&encoding)
+    }
+}
+
+fn read_expected_frame<R: Read>(
+    stream: &mut R,
+    expected_kind: u8,
+    max_frame_len: usize,
+) -> std::io::Result<Vec<u8>> {
+    match read_frame(stream, max_frame_len)? {
+        Some((kind, payload)) if kind == expected_kind => Ok(payload),
+        Some((kind, _)) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("unexpected frame {}", kind),
+        )),
+        None => Err(std::io::ErrorKind::UnexpectedEof.into()),
+    }
+}
+
+impl EncodedArtifactSender for TcpArtifactSender {
+    fn send(
+        &mut self,
+        mut encoded_artifact: Box<dyn Read>,
+        circuit_id: u8,
+    ) -> Result<(), std::io::Error> {
+        // the frame needs its length upfront
+        let mut payload = vec![];
+        encoded_artifact.read_to_end(&mut payload)?;
+        let len = payload.len();
+
+        self.send_with_len(Box::new(Cursor::new(payload)), len, circuit_id)
+    }
+
+    fn send_with_len(
+        &mut self,
+        mut encoded_artifact: Box<dyn Read>,
+        len: usize,
+        circuit_id: u8,
+    ) -> Result<(), std::io::Error> {
+        // an unacknowledged frame would otherwise be taken for the ack of this one
+        if self.pending_ack {
+            self.wait_for_acknowledgement(circuit_id)?;
+        }
+
+        let result = self.connect().and_then(|stream| {
+            write_frame_from(stream, FRAME_KIND_ARTIFACT, len, &mut encoded_artifact)
+        });
+        match result {
+            Ok(()) => {
+                self.pending_ack = true;
+                Ok(())
+            }
+            Err(e) => {
+                self.stream = None;
+                Err(e)
+            }
+        }
+    }
+
+    fn wait_for_acknowledgement(&mut self, circuit_id: u8) -> Result<(), std::io::Error> {
+        self.pending_ack = false;
+        self.read_ack().map_err(|e| {
+            println!(
+                "circuit {} was not acknowledged by {}: {}",
+                circuit_id, self.addr, e
+            );
+            self.stream = None;
+            e
+        })
+    }
+
+    fn credits(&mut self) -> Option<Credits> {
+        let mut result = Ok(());
+        if self.pending_ack {
+            self.pending_ack = false;
+            result = self.read_ack();
+        }
+        match result.and_then(|_| self.request_credits()) {
+            Ok(credits) => credits,
+            Err(e) => {
+                // an unreachable server has no room for anything
+                println!("failed requesting credits from {}: {}", self.addr, e);
+                self.stream = None;
+                Some(Credits {
+                    free_slots: 0,
+                    circuit_ids: None,
+                })
+            }
+        }
+    }
+}
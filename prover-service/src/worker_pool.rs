@@ -0,0 +1,170 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender},
+    },
+    thread::JoinHandle,
+};
+
+use super::*;
+
+/// Stages of the prover service that run on their own worker pool
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WorkerPoolKind {
+    Synthesis,
+    Decoding,
+    Proving,
+}
+
+#[derive(Clone, Debug)]
+pub struct WorkerPoolConfig {
+    pub num_workers: usize,
+    /// Number of tasks waiting for a worker before submitting blocks
+    pub queue_depth: usize,
+}
+
+/// Snapshot of the counters of a worker pool
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkerPoolStats {
+    pub queued: usize,
+    pub active: usize,
+    pub completed: usize,
+    pub panicked: usize,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    active: AtomicUsize,
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+    workers: AtomicUsize,
+}
+
+type Task = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of named threads working off a bounded queue. A panicking task
+/// is counted and doesn't take its worker down.
+pub(crate) struct WorkerPool {
+    name: String,
+    num_workers: usize,
+    sender: Mutex<Option<SyncSender<Task>>>,
+    receiver: Arc<Mutex<Receiver<Task>>>,
+    workers: Mutex<Vec<JoinHandle<()>>>,
+    counters: Arc<Counters>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(name: &str, config: WorkerPoolConfig) -> Self {
+        let (sender, receiver) = sync_channel::<Task>(config.queue_depth);
+        let pool = Self {
+            name: name.to_string(),
+            num_workers: config.num_workers.max(1),
+            sender: Mutex::new(Some(sender)),
+            receiver: Arc::new(Mutex::new(receiver)),
+            workers: Mutex::new(vec![]),
+            counters: Arc::new(Counters::default()),
+        };
+        for _ in 0..pool.num_workers {
+            pool.spawn_worker();
+        }
+
+        pool
+    }
+
+    fn spawn_worker(&self) {
+        let receiver = self.receiver.clone();
+        let counters = self.counters.clone();
+        let pool_name = self.name.clone();
+        let num_workers = self.num_workers;
+        let idx = counters.workers.fetch_add(1, Ordering::SeqCst);
+        let worker = std::thread::Builder::new()
+            .name(format!("{}-{}", self.name, idx))
+            .spawn(move || run_worker(&pool_name, num_workers, &receiver, &counters))
+            .expect("failed spawning worker thread");
+        let mut workers = self.workers.lock().unwrap();
+        workers.retain(|worker| !worker.is_finished());
+        workers.push(worker);
+    }
+
+    /// Makes up for a worker stuck in a hung task, the pool shrinks back
+    /// to its size once the stuck task returns. Returns false once every
+    /// worker has an outstanding replacement.
+    pub(crate) fn replace_stuck_worker(&self) -> bool {
+        if self.counters.workers.load(Ordering::SeqCst) >= 2 * self.num_workers {
+            println!("{} pool is out of replacement workers", self.name);
+            return false;
+        }
+        println!("adding a worker to {} pool", self.name);
+        self.spawn_worker();
+        true
+    }
+
+    /// Queues the task, blocks while the queue is full
+    pub(crate) fn execute<F: FnOnce() + Send + 'static>(&self, task: F) {
+        // cloned so that a blocked submitter doesn't hold the lock
+        let sender = self
+            .sender
+            .lock()
+            .unwrap()
+            .clone()
+            .unwrap_or_else(|| panic!("{} pool is shut down", self.name));
+        self.counters.queued.fetch_add(1, Ordering::SeqCst);
+        if sender.send(Box::new(task)).is_err() {
+            panic!("{} pool is shut down", self.name);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> WorkerPoolStats {
+        WorkerPoolStats {
+            queued: self.counters.queued.load(Ordering::SeqCst),
+            active: self.counters.active.load(Ordering::SeqCst),
+            completed: self.counters.completed.load(Ordering::SeqCst),
+            panicked: self.counters.panicked.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Lets the workers exit once the queue is empty, joins them if asked to
+    pub(crate) fn shutdown(&self, join: bool) {
+        self.sender.lock().unwrap().take();
+        let workers = std::mem::take(&mut *self.workers.lock().unwrap());
+        if join {
+            for worker in workers {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+fn run_worker(
+    pool_name: &str,
+    num_workers: usize,
+    receiver: &Mutex<Receiver<Task>>,
+    counters: &Counters,
+) {
+    loop {
+        let task = match receiver.lock().unwrap().recv() {
+            Ok(task) => task,
+            Err(_) => return,
+        };
+        counters.queued.fetch_sub(1, Ordering::SeqCst);
+        counters.active.fetch_add(1, Ordering::SeqCst);
+        if catch_unwind(AssertUnwindSafe(task)).is_err() {
+            counters.panicked.fetch_add(1, Ordering::SeqCst);
+            println!("task of {} pool panicked", pool_name);
+        }
+        counters.active.fetch_sub(1, Ordering::SeqCst);
+        counters.completed.fetch_add(1, Ordering::SeqCst);
+        // leave if a replacement took over while this worker was stuck
+        let is_surplus = counters
+            .workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| {
+                (workers > num_workers).then(|| workers - 1)
+            })
+            .is_ok();
+        if is_surplus {
+            return;
+        }
+    }
+}